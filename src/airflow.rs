@@ -0,0 +1,180 @@
+use nalgebra::Vector3;
+use serde_yaml::Value;
+
+/// Kind of local air flow source
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SourceKind
+{
+    /// rising air column from base up to height
+    THERMAL,
+    /// sinking air cell from height down to base with horizontal outflow near ground
+    DOWNDRAFT,
+    /// downward flow below rotor spreading with distance
+    WASH,
+}
+
+/// Radial strength profile of source
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SourceProfile
+{
+    /// full strength inside radius, none outside
+    UNIFORM,
+    /// strength falls linearly to zero at radius
+    LINEAR,
+    /// strength falls as exp(-(d/radius)^2), cut at three radii
+    GAUSSIAN,
+}
+
+/// Local air flow source: thermal, downdraft cell or rotor wash zone.
+/// Source is active from start for lifetime and fades in and out during fade time.
+#[derive(Clone, Debug)]
+pub struct AirSource
+{
+    pub kind: SourceKind,
+    pub profile: SourceProfile,
+    /// base of thermal and downdraft or rotor position in NED world frame in m
+    pub position: Vector3<f32>,
+    /// radius in m
+    pub radius: f32,
+    /// peak vertical speed in m/s
+    pub strength: f32,
+    /// vertical extent in m, upwards from base or downwards from rotor
+    pub height: f32,
+    /// simulation time of source start in s
+    pub start: f32,
+    /// lifetime in s, infinite if not set
+    pub lifetime: f32,
+    /// fade in and fade out time in s
+    pub fade: f32,
+    pub message: String,
+}
+
+impl AirSource
+{
+    /// Parses source from YAML: {kind, profile, position: [x, y, z], radius, strength, height, start, lifetime, fade, message}
+    /// kind: thermal, downdraft or rotor_wash. profile: uniform, linear or gaussian (default)
+    pub fn parse(value: &Value) -> Result<Self, String>
+    {
+        let number = |key: &str| value[key].as_f64().map(|v| v as f32);
+        let kind = match value["kind"].as_str().unwrap_or("") {
+            "thermal" => SourceKind::THERMAL,
+            "downdraft" => SourceKind::DOWNDRAFT,
+            "rotor_wash" => SourceKind::WASH,
+            kind => return Err(format!("Unknown air source kind: {}", kind)),
+        };
+        let profile = match value["profile"].as_str().unwrap_or("gaussian") {
+            "uniform" => SourceProfile::UNIFORM,
+            "linear" => SourceProfile::LINEAR,
+            "gaussian" => SourceProfile::GAUSSIAN,
+            profile => return Err(format!("Unknown air source profile: {}", profile)),
+        };
+        let position = value["position"].as_sequence()
+            .and_then(|seq| seq.iter().map(|v| v.as_f64().map(|v| v as f32)).collect::<Option<Vec<f32>>>())
+            .filter(|values| values.len() == 3)
+            .map(|values| Vector3::new(values[0], values[1], values[2]))
+            .ok_or("Air source: missing position")?;
+        let radius = number("radius").filter(|r| *r > 0.0).ok_or("Air source: missing or invalid radius")?;
+        let strength = number("strength").ok_or("Air source: missing strength")?;
+        let height = number("height").filter(|h| *h > 0.0).ok_or("Air source: missing or invalid height")?;
+        Ok(AirSource { kind, profile, position, radius, strength, height,
+            start: number("start").unwrap_or(0.0),
+            lifetime: number("lifetime").unwrap_or(f32::INFINITY),
+            fade: number("fade").unwrap_or(0.0).max(0.0),
+            message: value["message"].as_str().unwrap_or("").to_string() })
+    }
+
+    /// Returns strength multiplier in specified time: 0 if inactive, 1 if fully active
+    fn envelope(&self, time: f32) -> f32
+    {
+        let age = time - self.start;
+        if age < 0.0 || age > self.lifetime
+        {
+            return 0.0;
+        }
+        if self.fade <= 0.0
+        {
+            return 1.0;
+        }
+        (age / self.fade).min((self.lifetime - age) / self.fade).min(1.0)
+    }
+
+    /// Returns radial profile value for horizontal distance from axis and radius
+    fn radial(&self, distance: f32, radius: f32) -> f32
+    {
+        let x = distance / radius;
+        match self.profile {
+            SourceProfile::UNIFORM => if x <= 1.0 { 1.0 } else { 0.0 },
+            SourceProfile::LINEAR => (1.0 - x).max(0.0),
+            SourceProfile::GAUSSIAN => if x <= 3.0 { (-x * x).exp() } else { 0.0 },
+        }
+    }
+
+    /// Returns wind induced by source in specified position and time in m/s
+    pub fn wind(&self, pos: &Vector3<f32>, time: f32) -> Vector3<f32>
+    {
+        let envelope = self.envelope(time);
+        if envelope == 0.0
+        {
+            return Vector3::zeros();
+        }
+        let offset = pos - self.position;
+        let horizontal = Vector3::new(offset.x, offset.y, 0.0);
+        let distance = horizontal.norm();
+        let outward = if distance > f32::EPSILON { horizontal / distance } else { Vector3::zeros() };
+        // NED frame: altitude above base is negative z offset
+        let above = -offset.z;
+        let wind = match self.kind {
+            SourceKind::THERMAL => {
+                if above < 0.0 || above > self.height
+                {
+                    return Vector3::zeros();
+                }
+                // Updraft weakens in top tenth of column
+                let top = ((self.height - above) / (0.1 * self.height)).min(1.0);
+                Vector3::new(0.0, 0.0, -self.strength * self.radial(distance, self.radius) * top)
+            }
+            SourceKind::DOWNDRAFT => {
+                if above < 0.0 || above > self.height
+                {
+                    return Vector3::zeros();
+                }
+                // Near ground sinking air turns into horizontal outflow
+                let ground = (above / self.radius).min(1.0);
+                let profile = self.radial(distance, self.radius);
+                Vector3::new(0.0, 0.0, self.strength * profile * ground)
+                    + outward * self.strength * (1.0 - ground) * self.radial(distance, 2.0 * self.radius) * (distance / self.radius).min(1.0)
+            }
+            SourceKind::WASH => {
+                let below = -above;
+                if below < 0.0 || below > self.height
+                {
+                    return Vector3::zeros();
+                }
+                // Wash spreads and slows down with distance below rotor
+                let spread = 1.0 + below / self.height;
+                Vector3::new(0.0, 0.0, self.strength * self.radial(distance, self.radius * spread) / (spread * spread))
+            }
+        };
+        wind * envelope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_blow_in_expected_direction() {
+        let value: Value = serde_yaml::from_str("{kind: thermal, position: [0, 0, 0], radius: 10, strength: 3, height: 500, start: 10, lifetime: 100, fade: 10}").unwrap();
+        let thermal = AirSource::parse(&value).unwrap();
+        assert_eq!(thermal.wind(&Vector3::new(0.0, 0.0, -100.0), 5.0), Vector3::zeros());
+        assert_eq!(thermal.wind(&Vector3::new(0.0, 0.0, -100.0), 15.0), Vector3::new(0.0, 0.0, -1.5));
+        assert_eq!(thermal.wind(&Vector3::new(0.0, 0.0, -100.0), 50.0), Vector3::new(0.0, 0.0, -3.0));
+        assert_eq!(thermal.wind(&Vector3::new(0.0, 0.0, -600.0), 50.0), Vector3::zeros());
+        let value: Value = serde_yaml::from_str("{kind: downdraft, profile: linear, position: [0, 0, 0], radius: 10, strength: 5, height: 300}").unwrap();
+        let downdraft = AirSource::parse(&value).unwrap();
+        assert!(downdraft.wind(&Vector3::new(0.0, 0.0, -100.0), 0.0).z > 4.9);
+        assert!(downdraft.wind(&Vector3::new(8.0, 0.0, -1.0), 0.0).x > 0.0);
+        assert!(AirSource::parse(&serde_yaml::from_str("{kind: tornado}").unwrap()).is_err());
+    }
+}
@@ -1,13 +1,34 @@
-use std::{thread::{JoinHandle, self}, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, time};
+use std::{thread::{JoinHandle, self}, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, time::{self, Instant}};
+use std::collections::HashMap;
 use nalgebra::{Vector3, Matrix3};
-use rand::{rngs::ThreadRng, Rng};
-use crate::{drones::Drones, objects::Objects, config::ServerConfig};
+use crate::{drones::Drones, objects::Objects, config::ServerConfig, recorder::Recorder, simulation::{Simulation, StepPhase}};
+use crate::wind::WindGrid;
+use crate::weather::{Weather, WeatherState, weatherMsg};
+use crate::notification::{Notification, PromptCategory, PromptColor};
+use crate::turbulence::{TurbulenceModel, TurbulenceParams, RandomWalk, GustFilter};
 use crate::printLog;
 
 /// Air thermodynamic gas constant for dry air
 const R_AIR_CONSTANT: f32 = 287.052874;
-/// Temperature drop due to altitude rise
-const TEMP_ALTITUDE_RATE: f32 = 6.5e-3;
+/// Gas constant for water vapour
+const R_VAPOUR_CONSTANT: f32 = 461.495;
+/// Heat capacity ratio of air
+const HEAT_CAPACITY_RATIO: f32 = 1.4;
+/// Earth radius used to convert geometric altitude to geopotential altitude in m
+const EARTH_RADIUS: f32 = 6356766.0;
+/// Layers of International Standard Atmosphere: (base geopotential altitude in m, temperature gradient in K/m)
+const ISA_LAYERS: [(f32, f32); 7] = [
+    (0.0, -6.5e-3),
+    (11000.0, 0.0),
+    (20000.0, 1.0e-3),
+    (32000.0, 2.8e-3),
+    (47000.0, 0.0),
+    (51000.0, -2.8e-3),
+    (71000.0, -2.0e-3),
+];
+/// Sutherland's law constants for air: reference viscosity coefficient and Sutherland temperature
+const SUTHERLAND_BETA: f32 = 1.458e-6;
+const SUTHERLAND_TEMP: f32 = 110.4;
 /// Gravity constant on Earth
 pub const GRAVITY_ACCELERATION: f32 = 9.8067;
 
@@ -15,7 +36,26 @@ pub const GRAVITY_ACCELERATION: f32 = 9.8067;
 pub struct Atmosphere
 {
     running: Arc<AtomicBool>,
-    atmosphere_reqester: Option<thread::JoinHandle<()>>
+    atmosphere_reqester: Option<thread::JoinHandle<()>>,
+    wind_field: Arc<WindField>,
+}
+
+/// Mean wind field: wind grid or affine wind model, weather scenario and local air sources. Turbulence is not included.
+pub struct WindField
+{
+    wind_matrix: Matrix3<f32>,
+    wind_grid: Option<WindGrid>,
+    weather: Weather,
+}
+
+impl WindField
+{
+    /// Returns mean wind in m/s in specified position and simulation time
+    pub fn sample(&self, pos: &Vector3<f32>, time: f32) -> Vector3<f32>
+    {
+        let base = self.weather.at(time).wind;
+        Atmosphere::calcWind(pos, &self.wind_grid, &self.wind_matrix, &base) + self.weather.localWind(pos, time)
+    }
 }
 
 /// Atmosphere state DTO
@@ -24,12 +64,58 @@ pub struct AtmosphereInfo
 {
     /// wind vector in m/s
     pub wind: Vector3<f32>,
+    /// angular gust rates in rad/s
+    pub wind_angular: Vector3<f32>,
     /// air temperature in K
     pub air_temperature: f32,
     /// air pressure Pa
     pub air_pressure: f32,
     /// air_density kg/m3
     pub air_density: f32,
+    /// speed of sound in m/s
+    pub speed_of_sound: f32,
+    /// dynamic viscosity in Pa*s
+    pub dynamic_viscosity: f32,
+}
+
+impl AtmosphereInfo
+{
+    /// Builds atmosphere state from wind and air properties
+    fn new(wind: Vector3<f32>, wind_angular: Vector3<f32>, air: &AirState) -> Self
+    {
+        AtmosphereInfo {
+            wind,
+            wind_angular,
+            air_temperature: air.temperature,
+            air_pressure: air.pressure,
+            air_density: air.density,
+            speed_of_sound: air.speed_of_sound,
+            dynamic_viscosity: air.viscosity,
+        }
+    }
+}
+
+/// Site parameters of standard atmosphere
+#[derive(Copy, Clone, Debug)]
+struct Site
+{
+    /// altitude of map origin above sea level in m
+    elevation: f32,
+    /// temperature offset from standard atmosphere (ISA+dT) in K
+    temperature_offset: f32,
+    /// relative humidity from 0 to 1
+    humidity: f32,
+}
+
+/// Air properties in single point
+#[derive(Copy, Clone, Debug)]
+struct AirState
+{
+    temperature: f32,
+    pressure: f32,
+    density: f32,
+    speed_of_sound: f32,
+    viscosity: f32,
 }
 
 impl Atmosphere
@@ -43,86 +129,202 @@ impl Atmosphere
         let (wind_matrix, wind_bias) =  
             Atmosphere::parseWindFunction(&ServerConfig::get_str("wind_matrix"),
             &ServerConfig::get_str("wind_bias"));
+        let wind_grid_path = format!("assets/maps/{}/{}", ServerConfig::get_str("map"), ServerConfig::get_str("wind_grid"));
+        let wind_grid = if std::path::Path::new(&wind_grid_path).is_file()
+        {
+            let grid = WindGrid::load(&wind_grid_path).unwrap_or_else(|e| panic!("Unable to load wind grid {}", e));
+            printLog!("Wind grid loaded from {}", wind_grid_path);
+            Some(grid)
+        }
+        else
+        {
+            None
+        };
         let wind_turbulence_scale = ServerConfig::get_f32("wind_turbulence");
 
-        let T0 = ServerConfig::get_f32("temperature");
-        let p0 = ServerConfig::get_f32("pressure");
+        let initial_weather = WeatherState {
+            wind: wind_bias,
+            temperature: ServerConfig::get_f32("temperature"),
+            pressure: ServerConfig::get_f32("pressure"),
+        };
+        let weather_scenario = ServerConfig::get_str("weather_scenario");
+        let weather = if weather_scenario.is_empty()
+        {
+            Weather::constant(initial_weather)
+        }
+        else
+        {
+            let weather = Weather::load(&weather_scenario, initial_weather)
+                .unwrap_or_else(|e| panic!("Unable to load weather scenario {}", e));
+            printLog!("Weather scenario loaded from {}", weather_scenario);
+            weather
+        };
+        let turbulence_per_drone = ServerConfig::get_bool("wind_turbulence_per_drone");
+        let model_name = ServerConfig::get_str("wind_turbulence_model");
+        let turbulence_params = TurbulenceParams {
+            model: TurbulenceModel::parse(&model_name)
+                .unwrap_or_else(|| panic!("Unknown turbulence model: {}", model_name)),
+            wind_20ft: ServerConfig::get_f32("wind_turbulence_w20"),
+            sigma_high: ServerConfig::get_f32("wind_turbulence_sigma_high"),
+            span: ServerConfig::get_f32("wind_turbulence_span"),
+        };
+        let seed = Simulation::seed();
+        let site = Site {
+            elevation: ServerConfig::get_f32("field_elevation"),
+            temperature_offset: ServerConfig::get_f32("temperature_offset"),
+            humidity: ServerConfig::get_f32("humidity").clamp(0.0, 1.0),
+        };
+        let notify_period = ServerConfig::get_usize("notify_period") as u128;
+        let wind_field = Arc::new(WindField { wind_matrix, wind_grid, weather });
+        let field = wind_field.clone();
+
 
+        let drone_rate = ServerConfig::get_f32("atmosphere_drone_rate");
+        let object_rate = ServerConfig::get_f32("atmosphere_object_rate");
+        assert!(drone_rate > 0.0 && object_rate > 0.0, "Atmosphere update rates must be positive");
+        // Rates are given per second of simulation time, wall periods are shortened by time scale
+        let drone_period = time::Duration::from_secs_f32(1.0 / drone_rate);
+        let object_period = time::Duration::from_secs_f32(1.0 / object_rate);
 
         let atmosphere_reqester: JoinHandle<()> = thread::spawn(move ||
         {
-            let mut wind_turbulence_rng: [rand::rngs::ThreadRng; 3] = Default::default();
-            let mut turbulence = Vector3::zeros();
+            // Shared stream has number 0, drone streams are numbered by drone id
+            let mut shared_turbulence = RandomWalk::new(seed, 0);
+            let mut drone_turbulence = HashMap::<usize, RandomWalk>::new();
+            let mut drone_gusts = HashMap::<usize, GustFilter>::new();
+            let mut last_weather_time = f32::MIN;
+            let mut last_notify = Instant::now();
+            let mut next_drone_update = Instant::now();
+            let mut next_object_update = Instant::now();
             while r.load(Ordering::SeqCst) {
-                //Update aircrafts
-                let drones_lck = drones.lock().unwrap();
-                let pos = drones_lck.getPositions();
-                let mut infos = Vec::new();
-                Atmosphere::calcWindTurbulance(&mut turbulence,wind_turbulence_scale, &mut wind_turbulence_rng);
-                for p in pos
+                // In lockstep mode drones and objects are updated once per step in atmosphere phase
+                let lockstep = Simulation::isLockstep();
+                if lockstep
                 {
-                    let (air_temperature,air_pressure, air_density) = calcAirInfo(&p.1,T0,p0);
-                    let info = 
-                        AtmosphereInfo{
-                            wind: Atmosphere::calcWind(&p.1,&wind_matrix,&wind_bias) + turbulence,
-                            air_temperature,
-                            air_pressure,
-                            air_density
-                        };
-                    //println!("{:?}", info);
-                    infos.push((p.0,info));
+                    if !Simulation::waitTurn(StepPhase::ATMOSPHERE, &r)
+                    {
+                        continue;
+                    }
+                }
+                else if Simulation::isPaused()
+                {
+                    thread::sleep(time::Duration::from_millis(50));
+                    continue;
+                }
+                else
+                {
+                    let next = next_drone_update.min(next_object_update);
+                    let now = Instant::now();
+                    if next > now
+                    {
+                        thread::sleep((next - now).min(time::Duration::from_millis(50)));
+                        continue;
+                    }
                 }
-                thread::sleep(time::Duration::from_millis(50));
-                for (id,info) in infos.iter()
+                let update_drones = lockstep || Instant::now() >= next_drone_update;
+                let update_objects = lockstep || Instant::now() >= next_object_update;
+
+                //Update weather
+                let now = Simulation::now();
+                let weather_state = field.weather.at(now);
+                if field.weather.changed(last_weather_time, now)
                 {
-                    let d = drones_lck.drones.lock().unwrap();
-                    if let Some(drone) = d.iter().find(|drone| drone.id == *id)
+                    Notification::sendMsg(&weatherMsg(&weather_state));
+                    for message in field.weather.events(last_weather_time, now)
                     {
-                        drone.sendAtmosphereInfo(info);
+                        printLog!("Weather: {}", message);
+                        Notification::sendPrompt(-1, PromptCategory::WEATHER, PromptColor::BLUE, 5000, message);
+                    }
+                }
+                last_weather_time = now;
+
+                //Update aircrafts
+                if update_drones
+                {
+                    next_drone_update = Instant::now() + drone_period.div_f32(Simulation::getTimeScale());
+                    // Drones are locked only while positions and control handles are copied
+                    let drones_lck = drones.lock().unwrap();
+                    let pos = drones_lck.getPosOriVels();
+                    let controls = drones_lck.getControls();
+                    drop(drones_lck);
+                    let mut infos = Vec::new();
+                    let turbulence = shared_turbulence.next(wind_turbulence_scale);
+                    drone_turbulence.retain(|id, _| pos.iter().any(|p| p.0 == *id));
+                    drone_gusts.retain(|id, _| pos.iter().any(|p| p.0 == *id));
+                    for p in pos
+                    {
+                        let wind = field.sample(&p.1, now);
+                        let (turbulence, wind_angular) = match turbulence_params.model {
+                            TurbulenceModel::RANDOM if turbulence_per_drone => (drone_turbulence.entry(p.0)
+                                .or_insert_with(|| RandomWalk::new(seed, p.0 as u64))
+                                .next(wind_turbulence_scale), Vector3::zeros()),
+                            TurbulenceModel::RANDOM => (turbulence, Vector3::zeros()),
+                            _ => drone_gusts.entry(p.0)
+                                .or_insert_with(|| GustFilter::new(seed, p.0 as u64))
+                                .next(&turbulence_params, now, -p.1.z, &(p.3 - wind)),
+                        };
+                        let air = calcAirInfo(&p.1,weather_state.temperature,weather_state.pressure,&site);
+                        let info = AtmosphereInfo::new(wind + turbulence, wind_angular, &air);
+                        Recorder::recordAtmosphere("drone", p.0, &info.wind, info.air_temperature, info.air_pressure, info.air_density);
+                        infos.push((p.0,info));
+                    }
+                    if last_notify.elapsed().as_millis() > notify_period
+                    {
+                        last_notify = Instant::now();
+                        Notification::sendMsg(&atmosphereMsg(&infos));
+                    }
+                    for (id,info) in infos.iter()
+                    {
+                        if let Some(control) = controls.iter().find(|control| control.id == *id)
+                        {
+                            control.sendAtmosphereInfo(info);
+                        }
                     }
                 }
-                drop(drones_lck);
-                thread::sleep(time::Duration::from_millis(50));
 
                 //Update objects
-                let objects_lck = objects.lock().unwrap();
-                let pos = objects_lck.getPositions();
-                drop(objects_lck);
-                let wind: Vec<(usize,Vector3<f32>)> = pos.iter().map(|p| 
-                    (p.0,Atmosphere::calcWind(&p.1,&wind_matrix,&wind_bias))
-                ).collect();
-                if wind.is_empty()
+                if update_objects
                 {
-                    continue;
+                    next_object_update = Instant::now() + object_period.div_f32(Simulation::getTimeScale());
+                    let objects_lck = objects.lock().unwrap();
+                    let pos = objects_lck.getPositions();
+                    drop(objects_lck);
+                    let infos: Vec<(usize,AtmosphereInfo)> = pos.iter().map(|p| {
+                        let wind = field.sample(&p.1, now);
+                        let air = calcAirInfo(&p.1,weather_state.temperature,weather_state.pressure,&site);
+                        (p.0, AtmosphereInfo::new(wind, Vector3::zeros(), &air))
+                    }).collect();
+                    if !infos.is_empty()
+                    {
+                        for (id, info) in infos.iter()
+                        {
+                            Recorder::recordAtmosphere("object", *id, &info.wind, info.air_temperature, info.air_pressure, info.air_density);
+                        }
+                        let objects_lck = objects.lock().unwrap();
+                        objects_lck.updateAtmosphere(&infos);
+                        drop(objects_lck);
+                    }
+                }
+                if lockstep
+                {
+                    Simulation::finishTurn(StepPhase::ATMOSPHERE);
                 }
-                thread::sleep(time::Duration::from_millis(50));
-                let objects_lck = objects.lock().unwrap();
-                objects_lck.updateWind(wind);
-                drop(objects_lck);
-                thread::sleep(time::Duration::from_millis(50));
             }
         });
-        Atmosphere {running: running, atmosphere_reqester: Some(atmosphere_reqester) }
+        Atmosphere {running: running, atmosphere_reqester: Some(atmosphere_reqester), wind_field }
     }
 
-    /// Returns wind vector for specified position
-    fn calcWind(pos: &Vector3<f32>, windMatrix: &Matrix3<f32>, windBias: &Vector3<f32>) -> Vector3<f32>
+    /// Returns shared mean wind field. Used to sample wind for visualization
+    pub fn getWindField(&self) -> Arc<WindField>
     {
-        windBias + windMatrix * pos
+        self.wind_field.clone()
     }
 
-    /// Returns next turbulance vector
-    fn calcWindTurbulance(turbulence: &mut Vector3<f32>, turbulence_scale: f32, rng: &mut [ThreadRng; 3])
+    /// Returns wind vector for specified position. Wind grid is used if position is inside it, affine model otherwise
+    fn calcWind(pos: &Vector3<f32>, windGrid: &Option<WindGrid>, windMatrix: &Matrix3<f32>, windBias: &Vector3<f32>) -> Vector3<f32>
     {
-        if turbulence_scale < f32::EPSILON
-        {
-            return;
-        }
-        for i in 0..turbulence.len()
-        {
-            turbulence[i] = turbulence[i] + rng[i].gen_range(-turbulence_scale..turbulence_scale);
-            turbulence[i] = turbulence[i].clamp(-3.0 * turbulence_scale, 3.0 * turbulence_scale);
-        }
+        windGrid.as_ref().and_then(|grid| grid.sample(pos))
+            .unwrap_or_else(|| windBias + windMatrix * pos)
     }
 
 
@@ -162,13 +364,44 @@ impl Atmosphere
 }
 
 
-/// Calc air information for specified position
-fn calcAirInfo(pos: &Vector3<f32>, temp0: f32, pressure0: f32) -> (f32, f32, f32) {
-    let h = -pos[2];
-    let temp = calcTemperature(h,temp0);
+/// Serializes atmosphere state of drones to notification message:
+/// a:ID,WIND_X,WIND_Y,WIND_Z,TEMPERATURE,PRESSURE,DENSITY,GUST_P,GUST_Q,GUST_R,SPEED_OF_SOUND,VISCOSITY;...
+fn atmosphereMsg(infos: &[(usize, AtmosphereInfo)]) -> String
+{
+    let mut msg = String::with_capacity(2 + infos.len() * 120);
+    msg.push_str("a:");
+    for (id, info) in infos
+    {
+        msg.push_str(&id.to_string());
+        let values = [info.wind.x, info.wind.y, info.wind.z, info.air_temperature, info.air_pressure, info.air_density,
+            info.wind_angular.x, info.wind_angular.y, info.wind_angular.z, info.speed_of_sound, info.dynamic_viscosity];
+        for value in values
+        {
+            msg.push(',');
+            msg.push_str(&value.to_string());
+        }
+        msg.push(';');
+    }
+    msg
+}
+
+/// Calc air information for specified position. Sea level temperature and pressure are given
+fn calcAirInfo(pos: &Vector3<f32>, temp0: f32, pressure0: f32, site: &Site) -> AirState {
+    let altitude = site.elevation - pos[2];
+    let h = EARTH_RADIUS * altitude / (EARTH_RADIUS + altitude);
+    let temp = calcTemperature(h,temp0) + site.temperature_offset;
     let pressure = calcPressure(h,pressure0, temp0);
-    let density = calcDensity(temp, pressure);
-    return (temp,pressure,density);
+    let vapour_pressure = (site.humidity * calcSaturationPressure(temp)).min(pressure);
+    let density = calcHumidDensity(temp, pressure, vapour_pressure);
+    // Virtual temperature of dry air with the same density
+    let virtual_temp = pressure / (density * R_AIR_CONSTANT);
+    AirState {
+        temperature: temp,
+        pressure,
+        density,
+        speed_of_sound: (HEAT_CAPACITY_RATIO * R_AIR_CONSTANT * virtual_temp).sqrt(),
+        viscosity: calcViscosity(temp),
+    }
 }
 
 /// Calculates air density in kg/m3 for specified temperature and pressure
@@ -176,14 +409,60 @@ fn calcDensity(temp: f32, pressure: f32) -> f32 {
     pressure/(temp*R_AIR_CONSTANT)
 }
 
-/// Calculates air pressure in Pa for specified altitude
+/// Calculates density of humid air in kg/m3 for specified temperature, pressure and partial pressure of water vapour
+fn calcHumidDensity(temp: f32, pressure: f32, vapour_pressure: f32) -> f32 {
+    calcDensity(temp, pressure - vapour_pressure) + vapour_pressure / (temp * R_VAPOUR_CONSTANT)
+}
+
+/// Calculates saturation pressure of water vapour in Pa for specified temperature (Tetens equation)
+fn calcSaturationPressure(temp: f32) -> f32 {
+    610.78 * (17.27 * (temp - 273.15) / (temp - 35.86)).exp()
+}
+
+/// Calculates dynamic viscosity of air in Pa*s for specified temperature (Sutherland's law)
+fn calcViscosity(temp: f32) -> f32 {
+    SUTHERLAND_BETA * temp.powf(1.5) / (temp + SUTHERLAND_TEMP)
+}
+
+/// Calls function for every standard atmosphere layer crossed from sea level to specified geopotential altitude
+/// with layer temperature gradient and height of crossed part. Below sea level the first layer is extended.
+fn forEachLayer<F: FnMut(f32, f32)>(h: f32, mut f: F) {
+    for (i, (base, gradient)) in ISA_LAYERS.iter().enumerate()
+    {
+        let top = ISA_LAYERS.get(i + 1).map_or(f32::INFINITY, |layer| layer.0);
+        let dh = h.min(top) - base;
+        if dh <= 0.0 && i > 0
+        {
+            break;
+        }
+        f(*gradient, dh);
+    }
+}
+
+/// Calculates air pressure in Pa for specified geopotential altitude
 fn calcPressure(h: f32, pressure0: f32, temp0: f32) -> f32 {
-    pressure0 * (1.0 - TEMP_ALTITUDE_RATE * (h/temp0)).powf(GRAVITY_ACCELERATION/(R_AIR_CONSTANT*TEMP_ALTITUDE_RATE))
+    let mut temp = temp0;
+    let mut pressure = pressure0;
+    forEachLayer(h, |gradient, dh| {
+        if gradient == 0.0
+        {
+            pressure *= (-GRAVITY_ACCELERATION * dh / (R_AIR_CONSTANT * temp)).exp();
+        }
+        else
+        {
+            let next_temp = temp + gradient * dh;
+            pressure *= (next_temp / temp).powf(-GRAVITY_ACCELERATION / (R_AIR_CONSTANT * gradient));
+            temp = next_temp;
+        }
+    });
+    pressure
 }
 
-/// Calculates air temperature in K for specified altitude
+/// Calculates air temperature in K for specified geopotential altitude
 fn calcTemperature(h: f32, temp0: f32) -> f32 {
-    temp0 - h * TEMP_ALTITUDE_RATE
+    let mut temp = temp0;
+    forEachLayer(h, |gradient, dh| temp += gradient * dh);
+    temp
 }
 
 /// Deconstructor
@@ -224,4 +503,20 @@ mod tests {
         assert!((calcDensity(273.15 + 20.0,101325.0) - 1.204).abs() < EPS);
         assert!((calcDensity(273.15 + 15.0,101325.0) - 1.225).abs() < EPS);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn standard_atmosphere_layers_test() {
+        assert!((calcTemperature(11000.0,288.15) - 216.65).abs() < EPS);
+        assert!((calcTemperature(15000.0,288.15) - 216.65).abs() < EPS);
+        assert!((calcTemperature(25000.0,288.15) - 221.65).abs() < EPS);
+        assert!((calcPressure(11000.0,101325.0,288.15) - 22632.1).abs() < 2.0);
+        assert!((calcPressure(20000.0,101325.0,288.15) - 5474.89).abs() < 1.0);
+        let site = Site { elevation: 1000.0, temperature_offset: 10.0, humidity: 0.0 };
+        let air = calcAirInfo(&Vector3::zeros(), 288.15, 101325.0, &site);
+        assert!((air.temperature - 291.65).abs() < 0.1);
+        assert!((air.speed_of_sound - 342.4).abs() < 0.5);
+        assert!((calcViscosity(288.15) - 1.789e-5).abs() < 1e-7);
+        let humid = calcAirInfo(&Vector3::zeros(), 288.15, 101325.0, &Site { humidity: 1.0, ..site });
+        assert!(humid.density < air.density);
+    }
+}
@@ -0,0 +1,391 @@
+use nalgebra::Vector3;
+use crate::obj::Face;
+
+/// Number of bins used to evaluate surface area heuristic
+const SAH_BINS: usize = 12;
+/// Node with this number of faces or less is always a leaf
+const MIN_SPLIT_FACES: usize = 2;
+/// Node with more faces is split even if surface area heuristic prefers leaf
+const MAX_LEAF_FACES: usize = 8;
+
+/// Axis aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb
+{
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb
+{
+    /// Box that contains nothing. Merging with it returns the other box
+    pub fn empty() -> Self
+    {
+        Aabb { min: Vector3::repeat(f32::MAX), max: Vector3::repeat(f32::MIN) }
+    }
+
+    /// Cube around point
+    pub fn around(point: &Vector3<f32>, radius: f32) -> Self
+    {
+        Aabb { min: point.add_scalar(-radius), max: point.add_scalar(radius) }
+    }
+
+    /// Minimal box that contains face
+    pub fn ofFace(face: &Face) -> Self
+    {
+        let mut bounds = Self::empty();
+        face._vertices.iter().for_each(|v| bounds.grow(v));
+        bounds
+    }
+
+    /// Extends box to contain point
+    pub fn grow(&mut self, point: &Vector3<f32>)
+    {
+        self.min = self.min.inf(point);
+        self.max = self.max.sup(point);
+    }
+
+    /// Minimal box that contains both boxes
+    pub fn merge(&self, other: &Aabb) -> Self
+    {
+        Aabb { min: self.min.inf(&other.min), max: self.max.sup(&other.max) }
+    }
+
+    pub fn centroid(&self) -> Vector3<f32>
+    {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Surface area. Used as probability of hit in surface area heuristic
+    fn area(&self) -> f32
+    {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0
+        {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool
+    {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    /// Squared distance from point to box. Zero if point is inside
+    pub fn distanceSquared(&self, point: &Vector3<f32>) -> f32
+    {
+        (self.min - point).sup(&Vector3::zeros()).sup(&(point - self.max)).norm_squared()
+    }
+
+    /// Slab test. Returns distance along ray where it enters box if it happens before max_dist.
+    /// Returns 0 if origin is inside box.
+    pub fn rayEntry(&self, origin: &Vector3<f32>, dir: &Vector3<f32>, max_dist: f32) -> Option<f32>
+    {
+        let mut near = 0.0f32;
+        let mut far = max_dist;
+        for axis in 0..3
+        {
+            if dir[axis] == 0.0
+            {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis]
+                {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / dir[axis];
+            let t1 = (self.min[axis] - origin[axis]) * inv;
+            let t2 = (self.max[axis] - origin[axis]) * inv;
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+            if near > far
+            {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+/// Tree node. Inner node has two children: first is stored right after node, second at index
+struct Node
+{
+    bounds: Aabb,
+    /// first face in face order for leaf, second child for inner node
+    index: usize,
+    /// number of faces in leaf, 0 for inner node
+    count: usize,
+}
+
+/// Bounding volume hierarchy over map faces built with binned surface area heuristic.
+/// Tree stores face indices only, faces stay in Obj.
+pub struct Bvh
+{
+    nodes: Vec<Node>,
+    /// face indices ordered so that every leaf refers to continuous range
+    faces: Vec<usize>,
+}
+
+impl Bvh
+{
+    /// Builds tree over faces
+    pub fn build(faces: &[Face]) -> Self
+    {
+        let boxes: Vec<Aabb> = faces.iter().map(Aabb::ofFace).collect();
+        let centroids: Vec<Vector3<f32>> = boxes.iter().map(|b| b.centroid()).collect();
+        let mut bvh = Bvh { nodes: Vec::with_capacity(2 * faces.len()), faces: (0..faces.len()).collect() };
+        if !faces.is_empty()
+        {
+            bvh.split(&boxes, &centroids, 0, faces.len());
+        }
+        bvh
+    }
+
+    /// Creates node for faces in range [start, end) and splits it recursively. Returns node index
+    fn split(&mut self, boxes: &[Aabb], centroids: &[Vector3<f32>], start: usize, end: usize) -> usize
+    {
+        let bounds = self.faces[start..end].iter().fold(Aabb::empty(), |b, f| b.merge(&boxes[*f]));
+        let node = self.nodes.len();
+        let count = end - start;
+        self.nodes.push(Node { bounds, index: start, count });
+        if count <= MIN_SPLIT_FACES
+        {
+            return node;
+        }
+
+        let mut centroid_bounds = Aabb::empty();
+        self.faces[start..end].iter().for_each(|f| centroid_bounds.grow(&centroids[*f]));
+        let bin = |f: usize, axis: usize| {
+            let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+            let pos = (centroids[f][axis] - centroid_bounds.min[axis]) / extent;
+            ((pos * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+        };
+
+        // Best split as (cost, axis, last bin of first child)
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3
+        {
+            if centroid_bounds.max[axis] - centroid_bounds.min[axis] <= f32::EPSILON
+            {
+                continue;
+            }
+            let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+            for f in self.faces[start..end].iter()
+            {
+                let b = &mut bins[bin(*f, axis)];
+                b.0 = b.0.merge(&boxes[*f]);
+                b.1 += 1;
+            }
+            let mut first = [(0.0f32, 0usize); SAH_BINS - 1];
+            let mut acc = (Aabb::empty(), 0usize);
+            for i in 0..SAH_BINS - 1
+            {
+                acc = (acc.0.merge(&bins[i].0), acc.1 + bins[i].1);
+                first[i] = (acc.0.area(), acc.1);
+            }
+            acc = (Aabb::empty(), 0usize);
+            for i in (1..SAH_BINS).rev()
+            {
+                acc = (acc.0.merge(&bins[i].0), acc.1 + bins[i].1);
+                let (first_area, first_count) = first[i - 1];
+                if first_count == 0 || acc.1 == 0
+                {
+                    continue;
+                }
+                let cost = first_area * first_count as f32 + acc.0.area() * acc.1 as f32;
+                if best.is_none_or(|b| cost < b.0)
+                {
+                    best = Some((cost, axis, i - 1));
+                }
+            }
+        }
+
+        let (axis, last_bin) = match best {
+            Some((cost, _, _)) if cost >= bounds.area() * count as f32 && count <= MAX_LEAF_FACES => return node,
+            Some((_, axis, last_bin)) => (axis, last_bin),
+            // All centroids in one point. Split in half to keep leaves small
+            None if count > MAX_LEAF_FACES => (usize::MAX, 0),
+            None => return node,
+        };
+        let mut mid = start;
+        if axis == usize::MAX
+        {
+            mid = start + count / 2;
+        }
+        else
+        {
+            for i in start..end
+            {
+                if bin(self.faces[i], axis) <= last_bin
+                {
+                    self.faces.swap(i, mid);
+                    mid += 1;
+                }
+            }
+        }
+
+        self.split(boxes, centroids, start, mid);
+        let second = self.split(boxes, centroids, mid, end);
+        self.nodes[node].index = second;
+        self.nodes[node].count = 0;
+        node
+    }
+
+    /// Finds the closest face hit by ray not farther than max_dist. Returns distance along ray and face index.
+    /// Function hit is called only for faces which boxes are crossed by ray and returns distance if face is hit.
+    pub fn raycast(&self, origin: &Vector3<f32>, dir: &Vector3<f32>, max_dist: f32,
+        mut hit: impl FnMut(usize) -> Option<f32>) -> Option<(f32, usize)>
+    {
+        let mut best: Option<(f32, usize)> = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty()
+        {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop()
+        {
+            let node = &self.nodes[n];
+            let limit = best.map_or(max_dist, |b| b.0);
+            if node.bounds.rayEntry(origin, dir, limit).is_none()
+            {
+                continue;
+            }
+            if node.count > 0
+            {
+                for f in self.faces[node.index..node.index + node.count].iter()
+                {
+                    if let Some(dist) = hit(*f)
+                    {
+                        if dist < best.map_or(max_dist, |b| b.0)
+                        {
+                            best = Some((dist, *f));
+                        }
+                    }
+                }
+                continue;
+            }
+            // Visit the nearer child first, it is pushed last
+            let (first, second) = (n + 1, node.index);
+            let first_entry = self.nodes[first].bounds.rayEntry(origin, dir, limit);
+            let second_entry = self.nodes[second].bounds.rayEntry(origin, dir, limit);
+            match (first_entry, second_entry) {
+                (Some(a), Some(b)) if a <= b => stack.extend([second, first]),
+                (Some(_), Some(_)) => stack.extend([first, second]),
+                (Some(_), None) => stack.push(first),
+                (None, Some(_)) => stack.push(second),
+                (None, None) => {}
+            }
+        }
+        best
+    }
+
+    /// Calls visit for every face which box overlaps specified box
+    pub fn query(&self, bounds: &Aabb, mut visit: impl FnMut(usize))
+    {
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty()
+        {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop()
+        {
+            let node = &self.nodes[n];
+            if !node.bounds.overlaps(bounds)
+            {
+                continue;
+            }
+            if node.count > 0
+            {
+                self.faces[node.index..node.index + node.count].iter().for_each(|f| visit(*f));
+            }
+            else
+            {
+                stack.extend([node.index, n + 1]);
+            }
+        }
+    }
+
+    /// Finds face closest to point not farther than max_dist. Returns distance, closest point on face and face index
+    pub fn nearest(&self, faces: &[Face], point: &Vector3<f32>, max_dist: f32) -> Option<(f32, Vector3<f32>, usize)>
+    {
+        let mut best_sq = max_dist * max_dist;
+        let mut best: Option<(Vector3<f32>, usize)> = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty()
+        {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop()
+        {
+            let node = &self.nodes[n];
+            if node.bounds.distanceSquared(point) > best_sq
+            {
+                continue;
+            }
+            if node.count > 0
+            {
+                for f in self.faces[node.index..node.index + node.count].iter()
+                {
+                    let closest = faces[*f].closestPoint(point);
+                    let dist_sq = (closest - point).norm_squared();
+                    if dist_sq <= best_sq
+                    {
+                        best_sq = dist_sq;
+                        best = Some((closest, *f));
+                    }
+                }
+                continue;
+            }
+            let (first, second) = (n + 1, node.index);
+            if self.nodes[first].bounds.distanceSquared(point) <= self.nodes[second].bounds.distanceSquared(point)
+            {
+                stack.extend([second, first]);
+            }
+            else
+            {
+                stack.extend([first, second]);
+            }
+        }
+        best.map(|(closest, f)| (best_sq.sqrt(), closest, f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bvh_finds_nearest_hit_and_point() {
+        // Stack of horizontal quads at z = 0, -1, ..., -9, each made of two triangles
+        let mut faces = Vec::new();
+        for level in 0..10
+        {
+            let z = -(level as f32);
+            let v = [Vector3::new(0.0, 0.0, z), Vector3::new(10.0, 0.0, z), Vector3::new(10.0, 10.0, z), Vector3::new(0.0, 10.0, z)];
+            faces.push(Face::new(faces.len(), [v[0], v[1], v[2]], [Vector3::zeros(); 3]));
+            faces.push(Face::new(faces.len(), [v[0], v[2], v[3]], [Vector3::zeros(); 3]));
+        }
+        let bvh = Bvh::build(&faces);
+        let origin = Vector3::new(3.0, 2.0, -9.5);
+        let dir = Vector3::new(0.0, 0.0, 1.0);
+        let hit = bvh.raycast(&origin, &dir, 100.0, |f| match faces[f].rayIntersection(origin, dir) {
+            (true, dist) => Some(dist),
+            _ => None,
+        });
+        let (dist, face) = hit.unwrap();
+        assert!((dist - 0.5).abs() < 1e-5);
+        assert_eq!(faces[face]._vertices[0].z, -9.0);
+        assert!(bvh.raycast(&origin, &dir, 0.4, |f| faces[f].rayIntersection(origin, dir).0.then_some(0.5)).is_none());
+
+        let (dist, closest, _) = bvh.nearest(&faces, &Vector3::new(12.0, 5.0, -4.2), 10.0).unwrap();
+        assert!((dist - (4.0f32 + 0.04).sqrt()).abs() < 1e-5);
+        assert!((closest - Vector3::new(10.0, 5.0, -4.0)).norm() < 1e-5);
+        assert!(bvh.nearest(&faces, &Vector3::new(50.0, 5.0, 0.0), 10.0).is_none());
+
+        let mut found = Vec::new();
+        bvh.query(&Aabb::around(&Vector3::new(5.0, 5.0, -2.0), 0.5), |f| found.push(f));
+        found.sort();
+        assert_eq!(found, vec![4, 5]);
+    }
+}
@@ -1,7 +1,7 @@
 use std::{thread::{JoinHandle, self}, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, time, collections::HashMap};
 use nalgebra::{Vector3,geometry::Rotation3};
 use std::time::Instant;
-use crate::{drones::Drones, objects::Objects, config::ServerConfig, notification::Notification};
+use crate::{drones::Drones, objects::Objects, config::ServerConfig, notification::Notification, recorder::Recorder, simulation::{Simulation, StepPhase}};
 use crate::printLog;
 
 /// Parameters of link between UAV and Object. Flexible-damping rope model.
@@ -43,6 +43,18 @@ impl Cargo
         let collision_checker: JoinHandle<()> = thread::spawn(move ||
         {
             while r.load(Ordering::SeqCst) {
+                if Simulation::isLockstep()
+                {
+                    if !Simulation::waitTurn(StepPhase::CARGO, &r)
+                    {
+                        continue;
+                    }
+                }
+                else if Simulation::isPaused()
+                {
+                    thread::sleep(time::Duration::from_millis(2));
+                    continue;
+                }
                 let mut forceToSend = Vec::new();
 
                 let mut links_lck = l.lock().unwrap();
@@ -104,7 +116,7 @@ impl Cargo
 
                 if !forceToSend.is_empty()
                 {
-                    let obj_lck = _objects.lock().unwrap();
+                    let mut obj_lck = _objects.lock().unwrap();
                     for (_, obj_id, force, _) in &forceToSend {
                         obj_lck.setForce(*obj_id,-force);
                     }
@@ -112,8 +124,9 @@ impl Cargo
 
                     let mut drone_forces_to_send: HashMap<&usize, (Vector3::<f32>, Vector3::<f32>)> = HashMap::new();
 
-                    let drone_lck = _drones.lock().unwrap();
-                    for (drone_id, _, force, torque) in &forceToSend {
+                    let mut drone_lck = _drones.lock().unwrap();
+                    for (drone_id, obj_id, force, torque) in &forceToSend {
+                        Recorder::recordLink(*drone_id, *obj_id, force, torque);
 
                         if let Some(old_values) = drone_forces_to_send.get(drone_id)
                         {
@@ -131,7 +144,15 @@ impl Cargo
 
                     drop(drone_lck);
                 }
-                thread::sleep(time::Duration::from_millis(2));
+                if Simulation::isLockstep()
+                {
+                    Simulation::finishTurn(StepPhase::CARGO);
+                }
+                else
+                {
+                    // Loop runs with constant period of simulation time, so link timeout follows time scale
+                    thread::sleep(time::Duration::from_millis(2).div_f32(Simulation::getTimeScale()));
+                }
             }
         });
         Cargo {running, collision_checker: Some(collision_checker), links}
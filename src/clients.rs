@@ -6,11 +6,15 @@ use sha1::{Sha1, Digest};
 use serde_json::json;
 use regex::Regex;
 
-use crate::{drones::Drones, cargo::Cargo, config::ServerConfig, checksum::getChecksum};
-use crate::printLog;
+use nalgebra::Vector3;
+
+use crate::{drones::Drones, cargo::Cargo, config::ServerConfig, checksum::getChecksum, notification::Notification, simulation::Simulation, atmosphere::WindField};
+use crate::{printLog, printWarn, printError};
 
 /// Path to folder containing UAV's configurations
 const DRONE_CONFIGS_PATH: &str = "./configs/drones_configs/";
+/// Maximal number of points in one wind field query
+const MAX_WIND_SAMPLES: usize = 100_000;
 
 /// Handle simulation clients - visualizations
 pub struct Clients
@@ -24,7 +28,7 @@ pub struct Clients
 impl Clients
 {
     /// Contstuctor. Starts new process that handle incoming requests
-    pub fn new(_ctx: zmq::Context, drones: Arc<Mutex<Drones>>, cargo: Arc<Mutex<Cargo>>) -> Self {
+    pub fn new(_ctx: zmq::Context, drones: Arc<Mutex<Drones>>, cargo: Arc<Mutex<Cargo>>, wind_field: Arc<WindField>) -> Self {
         let hb_disconnect: usize = ServerConfig::get_usize("hb_disconnect");
         let replyer_port: usize = ServerConfig::get_usize("replyer_port");
         let first_port: usize = ServerConfig::get_usize("first_port");
@@ -126,7 +130,7 @@ impl Clients
                                     if let Err(_) = control_rep_socket.recv(&mut request, 0)
                                     {
                                         skipedHeartbeats += 1;
-                                        printLog!("Drone {}: Skipped heartbeat: {}", drone_no, skipedHeartbeats);
+                                        printWarn!("Drone {}: Skipped heartbeat: {}", drone_no, skipedHeartbeats);
                                         if skipedHeartbeats == hb_disconnect
                                         {
                                             let mut d_lck = d2.lock().unwrap();
@@ -181,7 +185,17 @@ impl Clients
                     'i' => {
                         replyer_socket.send(&Self::getServerInfo(), 0).unwrap();
                     },
-                    _ => printLog!("Unknown command: {}", request)
+                    'n' => {
+                        replyer_socket.send(&Self::getNotificationSnapshot(), 0).unwrap();
+                    },
+                    'a' => {
+                        let rep = Self::handleAdminMsg(&request, &drones);
+                        replyer_socket.send(&rep, 0).unwrap();
+                    },
+                    'w' => {
+                        replyer_socket.send(&Self::sampleWindField(&request, &wind_field), 0).unwrap();
+                    },
+                    _ => printWarn!("Unknown command: {}", request)
                 }
             }
         });
@@ -216,7 +230,7 @@ impl Clients
         }
         else 
         {
-            printLog!("Error: config directory can not be open.");    
+            printError!("Error: config directory can not be open.");    
         }
             
         let info = json!({
@@ -228,6 +242,90 @@ impl Clients
         serde_json::to_string(&info).unwrap()
     }
 
+    /// Returns last notifications as JSON array. Allows late joiners to restore state.
+    fn getNotificationSnapshot() -> String
+    {
+        serde_json::to_string(&Notification::getSnapshot()).unwrap()
+    }
+
+    /// Handle simulation admin command: a:pause, a:resume, a:scale;SCALE or a:status.
+    /// Returns simulation state as JSON or error.
+    fn handleAdminMsg(msg: &str, drones: &Arc<Mutex<Drones>>) -> String
+    {
+        let mut command = msg.splitn(2, ';');
+        let action = command.next().unwrap();
+        let param = command.next().and_then(|p| p.trim().parse::<f32>().ok());
+        let changed = match (action, param) {
+            ("a:pause", _) => { Simulation::setPaused(true); true },
+            ("a:resume", _) => { Simulation::setPaused(false); true },
+            ("a:scale", Some(scale)) if scale > 0.0 => { Simulation::setTimeScale(scale); true },
+            ("a:status", _) => false,
+            _ => {
+                printWarn!("Unknown admin command: {}", msg);
+                return "error".to_string();
+            }
+        };
+        if changed
+        {
+            let drones_lck = drones.lock().unwrap();
+            drones_lck.sendSimulationState();
+            drop(drones_lck);
+        }
+        let state = json!({
+            "paused": Simulation::isPaused(),
+            "time_scale": Simulation::getTimeScale()
+        });
+        serde_json::to_string(&state).unwrap()
+    }
+
+    /// Samples mean wind field on regular grid of points for visualization.
+    /// Request: w:ORIGIN_X,ORIGIN_Y,ORIGIN_Z;STEP_X,STEP_Y,STEP_Z;NX,NY,NZ
+    /// Returns JSON with simulation time and list of points [x, y, z, wind_x, wind_y, wind_z] or error.
+    fn sampleWindField(msg: &str, wind_field: &WindField) -> String
+    {
+        let Some(query) = msg.strip_prefix("w:") else {
+            printWarn!("Invalid wind field query: {}", msg);
+            return "error".to_string();
+        };
+        let params: Vec<Vec<&str>> = query.split(';').map(|part| part.split(',').map(|v| v.trim()).collect()).collect();
+        let vector = |part: &Vec<&str>| -> Option<Vector3<f32>> {
+            let values = part.iter().map(|v| v.parse::<f32>().ok()).collect::<Option<Vec<f32>>>()?;
+            (values.len() == 3).then(|| Vector3::new(values[0], values[1], values[2]))
+        };
+        let size = params.get(2)
+            .and_then(|part| part.iter().map(|v| v.parse::<usize>().ok()).collect::<Option<Vec<usize>>>())
+            .filter(|size| size.len() == 3);
+        let samples = size.as_ref()
+            .and_then(|size| size.iter().try_fold(1usize, |total, n| total.checked_mul(*n)))
+            .filter(|samples| *samples <= MAX_WIND_SAMPLES);
+        let (origin, step, size, samples) = match (params.first().and_then(vector), params.get(1).and_then(vector), size, samples) {
+            (Some(origin), Some(step), Some(size), Some(samples)) => (origin, step, size, samples),
+            _ => {
+                printWarn!("Invalid wind field query: {}", msg);
+                return "error".to_string();
+            }
+        };
+        let time = Simulation::now();
+        let mut points = Vec::with_capacity(samples);
+        for k in 0..size[2]
+        {
+            for j in 0..size[1]
+            {
+                for i in 0..size[0]
+                {
+                    let pos = origin + step.component_mul(&Vector3::new(i as f32, j as f32, k as f32));
+                    let wind = wind_field.sample(&pos, time);
+                    points.push([pos.x, pos.y, pos.z, wind.x, wind.y, wind.z]);
+                }
+            }
+        }
+        let field = json!({
+            "time": time,
+            "points": points
+        });
+        serde_json::to_string(&field).unwrap()
+    }
+
     /// Handle incomming control message
     fn handleControlMsg(msg: &str, drone_no: usize, drones: &mut Drones, cargo: &mut Cargo,  skipedHeartbeats: &mut usize) -> String
     {
@@ -289,7 +387,7 @@ impl Clients
                 }
                 _ => {
                     rep = "error".to_string();
-                    printLog!("Unknown command: {}", msg);
+                    printWarn!("Unknown command: {}", msg);
                 }
             }
         }
@@ -303,7 +401,7 @@ impl Clients
         {
             match std::fs::create_dir(&DRONE_CONFIGS_PATH) {
                 Ok(_) => printLog!("Drones config directory created"),
-                Err(_) => printLog!("Cannot create drones config directory"),
+                Err(_) => printError!("Cannot create drones config directory"),
             }
         }
     }
@@ -1,8 +1,12 @@
-use std::{thread::{JoinHandle, self}, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, time, collections::HashMap};
+use std::{thread::{JoinHandle, self}, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, time, collections::{HashMap, HashSet}};
 use nalgebra::{Vector3,Vector4, Matrix3, DMatrix};
 use std::time::Instant;
-use crate::{drones::Drones, objects::Objects, map::Map, config::ServerConfig, obj::Obj, notification::{Notification, PromptCategory, PromptColor}};
-use crate::printLog;
+use std::path::Path;
+use crate::{drones::Drones, objects::{Objects, ObjectKind}, map::{Map, MapIndex, MapParams, Surface}, bvh::Aabb, config::ServerConfig, obj::Obj, notification::{Notification, PromptCategory, PromptColor}, recorder::Recorder, simulation::{Simulation, StepPhase}};
+use crate::{printLog, printDebug, printError};
+
+/// Drone id, position, orientation quaternion, velocity and angular velocity
+type DroneKinematics = (usize,Vector3<f32>,Vector4<f32>,Vector3<f32>,Vector3<f32>);
 
 /// Detect collision in simulation. Checks collision uav-map, obj-map uav-uav and uav-obj.
 pub struct CollisionDetector
@@ -13,20 +17,13 @@ pub struct CollisionDetector
 
 impl CollisionDetector
 {
-    /// Constructor
-    pub fn new(_drones: Arc<Mutex<Drones>>, _objects: Arc<Mutex<Objects>>) -> Self
+    /// Loads map with its surfaces selected in configuration. Done before simulation processes are started
+    pub fn loadMap() -> Result<Map, String>
     {
-        let boundary_box_offset = ServerConfig::get_f32("boundaryBoxOffset");
-        let warn_boundary_box_offset = ServerConfig::get_f32("warnBoundaryBoxOffset");
-        let boundary_check_period = ServerConfig::get_usize("boundaryBoxCheckPeriod").try_into().unwrap();
-        let mut last_boundary_check = Instant::now();
         let mut map_path = "assets/maps/".to_string();
         map_path.push_str(ServerConfig::get_str("map").as_str());
         map_path.push_str("/model/model.obj");
 
-        let running = Arc::new(AtomicBool::new(true));
-        let r = running.clone();
-
         let grid = ServerConfig::get_str("grid").as_str().split(',')
         .map(|component| component.trim().parse())
         .collect::<Result<Vec<f32>, _>>()
@@ -37,15 +34,41 @@ impl CollisionDetector
             Vector3::new(components[0], components[1], components[2])
         }).unwrap();
 
-        let map = Map::new(&map_path,
-            ServerConfig::get_f32("collisionPlusEps"),
-            ServerConfig::get_f32("collisionMinusEps"),
-            grid,
-            ServerConfig::get_f32("COR"),
-            ServerConfig::get_f32("mi_s"),
-            ServerConfig::get_f32("mi_d"),
-            ServerConfig::get_f32("minimalDist"),
-        );
+        let index_name = ServerConfig::get_str("collisionIndex");
+        let index = MapIndex::parse(&index_name).unwrap_or_else(|| panic!("Unknown collision index: {}", index_name));
+        let params = MapParams {
+            collisionPlusEps: ServerConfig::get_f32("collisionPlusEps"),
+            collisionMinusEps: ServerConfig::get_f32("collisionMinusEps"),
+            COR: ServerConfig::get_f32("COR"),
+            mi_s: ServerConfig::get_f32("mi_s"),
+            mi_d: ServerConfig::get_f32("mi_d"),
+            minimalDist: ServerConfig::get_f32("minimalDist"),
+        };
+        let mut map = Map::new(&map_path, index, grid, params)?;
+        let surfaces_path = format!("configs/surfaces/{}.yaml", ServerConfig::get_str("map"));
+        if Path::new(&surfaces_path).is_file()
+        {
+            let materials = map.loadSurfaces(&surfaces_path).map_err(|e| format!("surfaces {}", e))?;
+            printLog!("Surface properties of {} map materials loaded from {}", materials, surfaces_path);
+        }
+        Ok(map)
+    }
+
+    /// Constructor
+    pub fn new(_drones: Arc<Mutex<Drones>>, _objects: Arc<Mutex<Objects>>, map: Map) -> Self
+    {
+        let boundary_box_offset = ServerConfig::get_f32("boundaryBoxOffset");
+        let warn_boundary_box_offset = ServerConfig::get_f32("warnBoundaryBoxOffset");
+        let boundary_check_period = ServerConfig::get_usize("boundaryBoxCheckPeriod").try_into().unwrap();
+        let mut last_boundary_check = Instant::now();
+        let mut last_boundary_sim_check = 0.0f32;
+        let lockstep_step = ServerConfig::get_usize("lockstep_step_time") as f32 / 1000.0;
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+
+        let object_collisions = ServerConfig::get_bool("objectCollisions");
+        let object_COR = ServerConfig::get_f32("objectCOR");
+        let object_friction = ServerConfig::get_f32("objectFriction");
         let (box_min, box_max) = map.getMinMax();
         let boundary_box_min = box_min.add_scalar(-boundary_box_offset);
         let boundary_box_max = box_max.add_scalar(boundary_box_offset);
@@ -57,35 +80,77 @@ impl CollisionDetector
             let mut loop_time = ServerConfig::get_f32("collisionLoopTime");
             let nominal_loop_time  = time::Duration::from_secs_f32(loop_time);
             let mut meshes = HashMap::<String,DMatrix<f32>>::new();
+            // Pairs of cargo and UAV that already collided
+            let mut cargo_hits = HashSet::<(usize,usize)>::new();
             while r.load(Ordering::SeqCst) {
                 let start = Instant::now();
-                let drones_lck = _drones.lock().unwrap();
+                if Simulation::isLockstep()
+                {
+                    if !Simulation::waitTurn(StepPhase::COLLISION, &r)
+                    {
+                        continue;
+                    }
+                }
+                else if Simulation::isPaused()
+                {
+                    thread::sleep(nominal_loop_time);
+                    continue;
+                }
+                // Simulation time passing during single loop
+                let sim_loop_time = if Simulation::isLockstep() { lockstep_step } else { loop_time * Simulation::getTimeScale() };
+                let mut drones_lck = _drones.lock().unwrap();
+                drones_lck.expireImpulses();
                 let drones_pos_vel = drones_lck.getPosOriVels();
                 let types = drones_lck.getTypes();
+                let bodies = drones_lck.getBodies();
                 drop(drones_lck);
 
-                let obj_lck = _objects.lock().unwrap();
+                let mut obj_lck = _objects.lock().unwrap();
+                obj_lck.expireImpulses();
                 let objs_pos_vels_radius = obj_lck.getPosVelsRadius();
                 drop(obj_lck);
                 
                 //Drone collision with map
-                Self::impulse_collision_drone(&drones_pos_vel,&_drones,&mut meshes, &types,&map,loop_time);
-                Self::impulse_collision_projectiles(&objs_pos_vels_radius,&_objects,&map,loop_time);
+                Self::impulse_collision_drone(&drones_pos_vel,&_drones,&mut meshes, &types,&map,sim_loop_time);
+                Self::impulse_collision_projectiles(&objs_pos_vels_radius,&_objects,&map,sim_loop_time);
+
 
+                for drone_type in types.iter()
+                {
+                    getMesh(&mut meshes, drone_type);
+                }
+                let poses: Vec<Option<DronePose>> = drones_pos_vel.iter().zip(types.iter())
+                    .map(|((_, pos, ori, _, _), drone_type)| DronePose::new(*pos, Self::quaterionToRot3(ori), &meshes[drone_type]))
+                    .collect();
 
-                //Collision between objects are negligible
-                Self::colisions_between_drones(&drones_pos_vel,map.minimalDist);
+                if object_collisions
+                {
+                    Self::colisions_between_objects(&objs_pos_vels_radius,&_objects,object_COR,object_friction,sim_loop_time);
+                }
+                Self::colisions_between_drones(&drones_pos_vel,&poses,&bodies,&_drones,map.COR,sim_loop_time);
                 Self::colisions_drones_obj(&drones_pos_vel, &objs_pos_vels_radius,map.minimalDist);
+                Self::hits_drones_obj(&drones_pos_vel,&poses,&objs_pos_vels_radius,&mut cargo_hits,&_objects,&_drones,sim_loop_time);
 
                 //Eliminate uav & objects outside boundary box
-                if last_boundary_check.elapsed().as_millis() > boundary_check_period
+                let boundary_check_due = if Simulation::isLockstep() {
+                    Simulation::now() - last_boundary_sim_check > boundary_check_period as f32 / 1000.0
+                } else {
+                    last_boundary_check.elapsed().as_millis() > boundary_check_period
+                };
+                if boundary_check_due
                 {
                     last_boundary_check = Instant::now();
+                    last_boundary_sim_check = Simulation::now();
                         Self::boundary_box_drones(&drones_pos_vel, &_drones, boundary_box_min,
                             boundary_box_max, warn_boundary_box_min,warn_boundary_box_max);
                         Self::boundary_box_obj(&objs_pos_vels_radius, &_objects, boundary_box_min, boundary_box_max);
                 }
-                              
+
+                if Simulation::isLockstep()
+                {
+                    Simulation::finishTurn(StepPhase::COLLISION);
+                    continue;
+                }
                 #[allow(unused_variables)]
                 let elapsed = start.elapsed();
                 if elapsed < nominal_loop_time
@@ -102,35 +167,89 @@ impl CollisionDetector
             }
     }
 
-    /// Find collisions between pair of UAVs
-    fn colisions_between_drones(drones_pos_vel: &Vec<(usize,Vector3<f32>,Vector4<f32>,Vector3<f32>,Vector3<f32>)>, minimal_dist: f32)
+    /// Find collisions between pair of UAVs. Mesh of every UAV is tested against hull of the other one.
+    /// Colliding UAVs exchange impulse according to their masses and inertias or are destroyed if configured.
+    fn colisions_between_drones(drones_pos_vel: &Vec<DroneKinematics>, poses: &[Option<DronePose>],
+        bodies: &[(usize,f32,Matrix3<f32>)], drones: &Arc<Mutex<Drones>>, COR: f32, loop_time: f32)
     {
+        let destroy = ServerConfig::get_bool("destroyOnDroneCollision");
+        let mut impulsesToSend = Vec::<(usize, Vector3<f32>, Vector3<f32>)>::new();
+        let mut dronesToKill = Vec::new();
         for i in 0..drones_pos_vel.len() {
             for j in (i+1)..drones_pos_vel.len() {
-                let obj1 = drones_pos_vel.get(i).unwrap();
-                let obj2 = drones_pos_vel.get(j).unwrap();
-                let dist: Vector3<f32> = obj1.1-obj2.1;
-                if dist.dot(&dist).abs() < minimal_dist
+                let (Some(first), Some(second)) = (&poses[i], &poses[j]) else {
+                    continue;
+                };
+                if (first.pos - second.pos).norm() > first.radius + second.radius
                 {
-                    Notification::sendPrompt(obj1.0 as isize, crate::notification::PromptCategory::COLLISION,
-                         crate::notification::PromptColor::RED, 2000, "COLLISION");
-                    Notification::sendPrompt(obj2.0 as isize, crate::notification::PromptCategory::COLLISION,
-                    crate::notification::PromptColor::RED, 2000, "COLLISION");
-                    //printLog!("Collision detected between drone {} and {}", obj1.0,obj2.0);
+                    continue;
+                }
+                let Some((point, normal)) = drone_contact(first, second) else {
+                    continue;
+                };
+                let (id1, id2) = (drones_pos_vel[i].0, drones_pos_vel[j].0);
+                Recorder::recordCollision("drone", id1, Some(id2), &point, &normal);
+                for id in [id1, id2]
+                {
+                    if destroy
+                    {
+                        Notification::sendPrompt(id as isize, PromptCategory::COLLISION, PromptColor::RED, 5000, "DESTROYED");
+                    }
+                    else
+                    {
+                        Notification::sendPrompt(id as isize, PromptCategory::COLLISION, PromptColor::RED, 2000, "COLLISION");
+                    }
+                }
+                if destroy
+                {
+                    dronesToKill.extend([id1, id2]);
+                    continue;
                 }
+                let contactBody = |k: usize, pose: &DronePose| {
+                    let (_, mass, inertia) = bodies.iter().find(|b| b.0 == drones_pos_vel[k].0)?;
+                    let (_, _, _, vel, angVel) = drones_pos_vel[k];
+                    let arm = point - pose.pos;
+                    Some(ContactBody {
+                        inv_mass: if *mass > 0.0 { 1.0 / mass } else { 0.0 },
+                        inv_inertia: pose.rot * inertia.try_inverse().unwrap_or(Matrix3::zeros()) * pose.rot.transpose(),
+                        arm,
+                        velocity: pose.rot * (vel + angVel.cross(&(pose.rot.transpose() * arm))),
+                    })
+                };
+                let (Some(body1), Some(body2)) = (contactBody(i, first), contactBody(j, second)) else {
+                    continue;
+                };
+                let impulse = contact_impulse(&body1, &body2, &normal, COR);
+                if impulse != Vector3::zeros()
+                {
+                    impulsesToSend.push((id1, impulse, body1.arm.cross(&impulse)));
+                    impulsesToSend.push((id2, -impulse, body2.arm.cross(&-impulse)));
+                }
+            }
+        }
+        if !impulsesToSend.is_empty() || !dronesToKill.is_empty()
+        {
+            let mut drones_lck = drones.lock().unwrap();
+            for (id, impulse, angularImpulse) in &impulsesToSend {
+                drones_lck.applyImpulse(id, impulse, angularImpulse, loop_time);
             }
+            for id in dronesToKill {
+                drones_lck.removeUAV(id);
+            }
+            drop(drones_lck);
         }
     }
 
     /// Find collisions between UAV and object
-    fn colisions_drones_obj(drones_pos_vel: &Vec<(usize,Vector3<f32>,Vector4<f32>,Vector3<f32>,Vector3<f32>)>,
+    fn colisions_drones_obj(drones_pos_vel: &Vec<DroneKinematics>,
         objs_pos_vels: &Vec<(usize,Vector3<f32>,Vector3<f32>,f32)>, minimal_dist: f32)
     {
         for obj1 in drones_pos_vel.iter() {
             for obj2 in objs_pos_vels.iter() {
                 let dist: Vector3<f32> = obj1.1-obj2.1;
-                if dist.dot(&obj2.2) > 0.0 && dist.dot(&dist).abs() < minimal_dist
+                if dist.dot(&obj2.2) > 0.0 && dist.norm() < minimal_dist
                 {
+                    Recorder::recordCollision("object_near", obj1.0, Some(obj2.0), &obj2.1, &Vector3::repeat(f32::NAN));
                     Notification::sendPrompt(obj1.0 as isize, crate::notification::PromptCategory::COLLISION,
                         crate::notification::PromptColor::ORANGE, 2000, "OBJECT DETECTED");
                     //printLog!("Collision detected between drone {} and object {}", obj1.0,obj2.0);
@@ -139,9 +258,103 @@ impl CollisionDetector
         }
     }
 
+    /// Find hits of objects released by UAVs on other UAVs. Motion of object relative to UAV during loop time is swept
+    /// against UAV hull. Ammunition is removed after hit, cargo hits every UAV only once.
+    /// Hit UAV loses hit points according to impact energy and is removed when none left. Marker hits are only reported
+    fn hits_drones_obj(drones_pos_vel: &[DroneKinematics], poses: &[Option<DronePose>],
+        objs_pos_vels: &[(usize,Vector3<f32>,Vector3<f32>,f32)], cargo_hits: &mut HashSet<(usize,usize)>,
+        objects: &Arc<Mutex<Objects>>, drones: &Arc<Mutex<Drones>>, loop_time: f32)
+    {
+        cargo_hits.retain(|(obj_id, _)| objs_pos_vels.iter().any(|obj| obj.0 == *obj_id));
+        let mut hits = Vec::new();
+        for ((id, _, _, vel, _), pose) in drones_pos_vel.iter().zip(poses.iter()) {
+            let Some(pose) = pose else {
+                continue;
+            };
+            let drone_vel = pose.rot * vel;
+            for (obj_id, obj_pos, obj_vel, radius) in objs_pos_vels.iter() {
+                let rel_vel = obj_vel - drone_vel;
+                if (obj_pos - pose.pos).norm() > pose.radius + radius + rel_vel.norm() * loop_time
+                    || cargo_hits.contains(&(*obj_id, *id))
+                    || hits.iter().any(|(_, hit_obj, _, _, _)| hit_obj == obj_id)
+                {
+                    continue;
+                }
+                let Some(point) = object_hit(pose, obj_pos, &rel_vel, *radius, loop_time) else {
+                    continue;
+                };
+                let obj_lck = objects.lock().unwrap();
+                let info = obj_lck.getInfo(*obj_id);
+                drop(obj_lck);
+                match info {
+                    Some(info) if info.owner != *id => hits.push((*id, *obj_id, info, point, rel_vel)),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut objectsToRemove = Vec::new();
+        let mut dronesToKill = Vec::new();
+        for (id, obj_id, info, point, rel_vel) in hits {
+            match info.kind {
+                ObjectKind::AMMO => objectsToRemove.push(obj_id),
+                ObjectKind::CARGO => { cargo_hits.insert((obj_id, id)); },
+            }
+            let normal = rel_vel.try_normalize(f32::EPSILON).unwrap_or(Vector3::zeros());
+            if info.marker
+            {
+                Recorder::recordCollision("marker_hit", id, Some(obj_id), &point, &normal);
+                Notification::sendPrompt(id as isize, PromptCategory::DAMAGE, PromptColor::ORANGE, 2000, "MARKED");
+                Notification::sendPrompt(info.owner as isize, PromptCategory::DAMAGE, PromptColor::GREEN, 2000, "TARGET MARKED");
+                continue;
+            }
+            Recorder::recordCollision("object_hit", id, Some(obj_id), &point, &normal);
+            let energy = 0.5 * info.mass * rel_vel.norm_squared();
+            let mut drones_lck = drones.lock().unwrap();
+            let damage = drones_lck.applyDamage(&id, energy);
+            drop(drones_lck);
+            match damage {
+                Some((_, hit_points)) if hit_points <= 0.0 => {
+                    if !dronesToKill.contains(&id)
+                    {
+                        printLog!("UAV {} destroyed by object {} of UAV {}", id, obj_id, info.owner);
+                        Notification::sendPrompt(id as isize, PromptCategory::DAMAGE, PromptColor::RED, 5000, "DESTROYED");
+                        Notification::sendPrompt(info.owner as isize, PromptCategory::DAMAGE, PromptColor::GREEN, 5000, "TARGET DESTROYED");
+                        dronesToKill.push(id);
+                    }
+                },
+                Some((damage, hit_points)) => {
+                    let msg = format!("HIT -{:.0} HP {:.0}", damage, hit_points);
+                    Notification::sendPrompt(id as isize, PromptCategory::DAMAGE, PromptColor::RED, 2000, &msg);
+                    Notification::sendPrompt(info.owner as isize, PromptCategory::DAMAGE, PromptColor::GREEN, 2000, "TARGET HIT");
+                },
+                None => {
+                    Notification::sendPrompt(id as isize, PromptCategory::DAMAGE, PromptColor::RED, 2000, "HIT");
+                    Notification::sendPrompt(info.owner as isize, PromptCategory::DAMAGE, PromptColor::GREEN, 2000, "TARGET HIT");
+                },
+            }
+        }
+        if !objectsToRemove.is_empty()
+        {
+            let mut obj_lck = objects.lock().unwrap();
+            for obj_id in objectsToRemove {
+                obj_lck.removeObj(obj_id);
+            }
+            drop(obj_lck);
+        }
+        if !dronesToKill.is_empty()
+        {
+            let mut drones_lck = drones.lock().unwrap();
+            for id in dronesToKill {
+                drones_lck.removeUAV(id);
+            }
+            drop(drones_lck);
+        }
+    }
+
     #[allow(dead_code)]
     /// Find uav outside boundary box and remove them
-    fn boundary_box_drones(drones_pos_vel_ori: &Vec<(usize,Vector3<f32>,Vector4<f32>,Vector3<f32>,Vector3<f32>)>,
+    fn boundary_box_drones(drones_pos_vel_ori: &Vec<DroneKinematics>,
         objects: &Arc<Mutex<Drones>>, box_min: Vector3<f32>, box_max: Vector3<f32>,
         warn_box_min: Vector3<f32>, warn_box_max: Vector3<f32>)
     {
@@ -152,6 +365,7 @@ impl CollisionDetector
 
             if box_min.inf(pos) != box_min || box_max.sup(pos) != box_max
             {
+                Recorder::recordCollision("boundary", *id, None, pos, &Vector3::repeat(f32::NAN));
                 dronesToKill.push(id);
                 continue;
             }
@@ -188,13 +402,14 @@ impl CollisionDetector
         {
             if box_min.inf(pos) != box_min || box_max.sup(pos) != box_max
             {
-                printLog!("Object {} is outside the boundary box", id);
+                printDebug!("Object {} is outside the boundary box", id);
+                Recorder::recordCollision("object_boundary", *id, None, pos, &Vector3::repeat(f32::NAN));
                 objToKill.push(id);
             }
         }
         if !objToKill.is_empty()
         {
-            let obj_lck = objects.lock().unwrap();
+            let mut obj_lck = objects.lock().unwrap();
             for id in objToKill {
                 obj_lck.removeObj(*id);
             }
@@ -202,22 +417,65 @@ impl CollisionDetector
         }
     }
 
+    /// Find collisions between objects treated as spheres of collision radius. Candidate pairs are found with spatial hash.
+    /// Colliding objects exchange impulse with restitution and friction, spread over loop time as outer force
+    fn colisions_between_objects(objs_pos_vels_radius: &[(usize,Vector3<f32>,Vector3<f32>,f32)],
+        objects: &Arc<Mutex<Objects>>, COR: f32, friction: f32, loop_time: f32)
+    {
+        let pairs = overlapping_spheres(objs_pos_vels_radius);
+        if pairs.is_empty()
+        {
+            return;
+        }
+        let mut obj_lck = objects.lock().unwrap();
+        for (i, j) in pairs {
+            let sphere = |k: usize| {
+                let (id, pos, vel, radius) = objs_pos_vels_radius[k];
+                let mass = obj_lck.getInfo(id).map_or(0.0, |info| info.mass);
+                Sphere { pos, vel, radius, inv_mass: if mass > 0.0 { 1.0 / mass } else { 0.0 } }
+            };
+            let (first, second) = (sphere(i), sphere(j));
+            let Some((impulse, point, normal)) = sphere_impulse(&first, &second, COR, friction, loop_time) else {
+                continue;
+            };
+            let (id1, id2) = (objs_pos_vels_radius[i].0, objs_pos_vels_radius[j].0);
+            Recorder::recordCollision("object", id1, Some(id2), &point, &normal);
+            obj_lck.applyImpulse(id1, &impulse, loop_time);
+            obj_lck.applyImpulse(id2, &-impulse, loop_time);
+        }
+        drop(obj_lck);
+    }
+
     /// Find all collision between Object and map walls. Handles collision
     fn impulse_collision_projectiles(objs_pos_vels_radius: &Vec<(usize,Vector3<f32>,Vector3<f32>,f32)>,
     objects: &Arc<Mutex<Objects>>, map: &Map, loop_time: f32)
     {
-        let mut collisionsToSend = Vec::<(usize, Vector3<f32>)>::new();
+        let mut collisionsToSend = Vec::<(usize, Vector3<f32>, Surface)>::new();
+        let mut objToKill = Vec::new();
 
-        //For every drone
+        //For every object
         for (id, pos, vel,radius) in objs_pos_vels_radius.iter()
         {
-            collisionsToSend.extend(map.checkWalls2(*pos,*vel,loop_time, *radius).iter().map(|n| (*id,n.clone())));
+            if let Some((_, contactPoint, normal, surface)) = map.sweepSphere(*pos,*vel,loop_time, *radius)
+            {
+                if surface.deadly
+                {
+                    Recorder::recordCollision("object_deadly", *id, None, &contactPoint, &normal);
+                    objToKill.push(*id);
+                    continue;
+                }
+                Recorder::recordCollision("object_terrain", *id, None, &contactPoint, &normal);
+                collisionsToSend.push((*id, normal, surface));
+            }
         }
-        if !collisionsToSend.is_empty()
+        if !collisionsToSend.is_empty() || !objToKill.is_empty()
         {
-            let objects_lck = objects.lock().unwrap();
-            for (id, normalVector) in &collisionsToSend {
-                objects_lck.sendSurfaceCollison(*id, map.COR, map.mi_s, map.mi_d, normalVector);
+            let mut objects_lck = objects.lock().unwrap();
+            for (id, normalVector, surface) in &collisionsToSend {
+                objects_lck.sendSurfaceCollison(*id, surface.COR, surface.mi_s, surface.mi_d, normalVector);
+            }
+            for id in objToKill {
+                objects_lck.removeObj(id);
             }
             drop(objects_lck);
         }
@@ -232,10 +490,10 @@ impl CollisionDetector
     }
 
     /// Find all collision between UAV and map walls. Handles collision
-    fn impulse_collision_drone(uav_pos_vels: &Vec<(usize,Vector3<f32>,Vector4<f32>,Vector3<f32>,Vector3<f32>)>,
+    fn impulse_collision_drone(uav_pos_vels: &Vec<DroneKinematics>,
         drones: &Arc<Mutex<Drones>>,meshes: &mut HashMap<String,DMatrix<f32>>, types: &Vec<String>, map: &Map, loop_time: f32)
     {
-        let mut collisionsToSend = Vec::<(usize, Vector3<f32>, Vector3<f32>)>::new();
+        let mut collisionsToSend = Vec::<(usize, Vector3<f32>, Vector3<f32>, Surface)>::new();
         
         //For every drone
         for ((id, pos, ori, vel, angVel),drone_type) in uav_pos_vels.iter().zip(types.iter())
@@ -243,12 +501,13 @@ impl CollisionDetector
             let mut best_depth = f32::MAX;
             let mut best_point: Vector3<f32> = Vector3::zeros();
             let mut best_normal: Vector3<f32> = Vector3::zeros();
+            let mut best_surface = None;
             let rot = Self::quaterionToRot3(ori);
             let mesh = getMesh(meshes,drone_type);
             mesh.column_iter().for_each(|col| {
                 let point = rot * col + pos;
                 let point_vel =  rot * (vel + angVel.cross(&col));
-                if let Some((depth,normal)) = 
+                if let Some((depth,normal,surface)) = 
                     map.checkWallsBest2(point,point_vel, loop_time)
                 {
                     if depth < best_depth
@@ -256,34 +515,44 @@ impl CollisionDetector
                         best_depth = depth;
                         best_normal = normal;
                         best_point = point;
+                        best_surface = Some(surface);
                     }
                 }
             });
-            if best_depth < f32::MAX
+            if let Some(surface) = best_surface
             {
-                collisionsToSend.push((*id,best_point,best_normal));
+                collisionsToSend.push((*id,best_point,best_normal,surface));
             }
         }
         if !collisionsToSend.is_empty()
         {
             let mut drones_lck = drones.lock().unwrap();
-            if ServerConfig::get_bool("destroyOnCollision")
-            {
-                for (id,_, _) in &collisionsToSend {
+            let destroy = ServerConfig::get_bool("destroyOnCollision");
+            for (id,colisionPoint, normalVector, surface) in &collisionsToSend {
+                if destroy || surface.deadly
+                {
+                    Recorder::recordCollision("terrain_destroyed", *id, None, colisionPoint, normalVector);
                     drones_lck.removeUAV(*id);
                     Notification::sendPrompt((*id) as isize, PromptCategory::TERRAIN,
                             PromptColor::RED ,
                             5000, "DESTROYED");
                 }
-            }
-            else
-            {
-                for (id,colisionPoint, normalVector) in &collisionsToSend {
-                    drones_lck.sendSurfaceCollison(id, map.COR, map.mi_s, map.mi_d, colisionPoint, normalVector);
+                else if surface.landing_pad
+                {
+                    Recorder::recordCollision("landing_pad", *id, None, colisionPoint, normalVector);
+                    drones_lck.sendSurfaceCollison(id, surface.COR, surface.mi_s, surface.mi_d, colisionPoint, normalVector);
+                    Notification::sendPrompt((*id) as isize, PromptCategory::TERRAIN,
+                        PromptColor::GREEN ,
+                        2000, "LANDING PAD");
+                }
+                else
+                {
+                    Recorder::recordCollision("terrain", *id, None, colisionPoint, normalVector);
+                    drones_lck.sendSurfaceCollison(id, surface.COR, surface.mi_s, surface.mi_d, colisionPoint, normalVector);
                     Notification::sendPrompt((*id) as isize, PromptCategory::TERRAIN,
                         PromptColor::ORANGE ,
                         2000, "TERRAIN COLLISION");
-                } 
+                }
             }
             
             drop(drones_lck);
@@ -292,11 +561,206 @@ impl CollisionDetector
 
 }
 
+/// UAV pose with mesh and its hull used in contact detection. Hull is box bounding mesh in body frame
+struct DronePose<'a>
+{
+    pos: Vector3<f32>,
+    /// rotation from body to world frame
+    rot: Matrix3<f32>,
+    mesh: &'a DMatrix<f32>,
+    hull_min: Vector3<f32>,
+    hull_max: Vector3<f32>,
+    /// radius of sphere around hull
+    radius: f32,
+}
+
+impl<'a> DronePose<'a>
+{
+    /// Constructor. Returns None if mesh is empty
+    fn new(pos: Vector3<f32>, rot: Matrix3<f32>, mesh: &'a DMatrix<f32>) -> Option<Self>
+    {
+        if mesh.ncols() == 0
+        {
+            return None;
+        }
+        let hull_min: Vector3<f32> = Vector3::from_fn(|axis, _| mesh.row(axis).min());
+        let hull_max: Vector3<f32> = Vector3::from_fn(|axis, _| mesh.row(axis).max());
+        let radius = hull_min.abs().sup(&hull_max.abs()).norm();
+        Some(DronePose { pos, rot, mesh, hull_min, hull_max, radius })
+    }
+}
+
+/// Sweeps object moving with velocity relative to UAV during dt against UAV hull expanded by object radius.
+/// Returns point in world frame where object hits hull
+fn object_hit(pose: &DronePose, pos: &Vector3<f32>, rel_vel: &Vector3<f32>, radius: f32, dt: f32) -> Option<Vector3<f32>>
+{
+    let toBody = pose.rot.transpose();
+    let start = toBody * (pos - pose.pos);
+    let motion = toBody * rel_vel * dt;
+    let length = motion.norm();
+    let dir = if length > 0.0 { motion / length } else { Vector3::zeros() };
+    let hull = Aabb { min: pose.hull_min.add_scalar(-radius), max: pose.hull_max.add_scalar(radius) };
+    let entry = hull.rayEntry(&start, &dir, length)?;
+    Some(pose.pos + pose.rot * (start + dir * entry))
+}
+
+/// Fraction of spheres penetration removed by separating velocity in single loop
+const PENETRATION_CORRECTION: f32 = 0.2;
+
+/// Object treated as sphere in collision
+struct Sphere
+{
+    pos: Vector3<f32>,
+    vel: Vector3<f32>,
+    radius: f32,
+    inv_mass: f32,
+}
+
+/// Broad phase of collisions between objects. Spheres are hashed into cells of size of the largest diameter,
+/// so only neighbour cells have to be checked. Returns pairs of indices of overlapping spheres
+fn overlapping_spheres(objs_pos_vels_radius: &[(usize,Vector3<f32>,Vector3<f32>,f32)]) -> Vec<(usize,usize)>
+{
+    let mut pairs = Vec::new();
+    if objs_pos_vels_radius.len() < 2
+    {
+        return pairs;
+    }
+    let max_radius = objs_pos_vels_radius.iter().fold(0.0f32, |max, obj| max.max(obj.3));
+    let cell_size = (2.0 * max_radius).max(1e-3);
+    let cellOf = |pos: &Vector3<f32>| pos.map(|x| (x / cell_size).floor() as i64);
+    let mut cells = HashMap::<Vector3<i64>,Vec<usize>>::new();
+    for (index, obj) in objs_pos_vels_radius.iter().enumerate() {
+        cells.entry(cellOf(&obj.1)).or_default().push(index);
+    }
+    for (i, (_, pos, _, radius)) in objs_pos_vels_radius.iter().enumerate() {
+        let cell = cellOf(pos);
+        for offset in (0..27).map(|k| Vector3::new(k % 3 - 1, k / 3 % 3 - 1, k / 9 - 1)) {
+            let Some(neighbours) = cells.get(&(cell + offset)) else {
+                continue;
+            };
+            for &j in neighbours.iter().filter(|&&j| j > i) {
+                let (_, other_pos, _, other_radius) = objs_pos_vels_radius[j];
+                if (pos - other_pos).norm() < radius + other_radius
+                {
+                    pairs.push((i, j));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Calculates impulse applied to first sphere in collision with second one. Normal impulse restores relative velocity
+/// with COR and removes part of penetration, tangential impulse is limited by friction.
+/// Returns impulse, contact point and normal pointing from second to first sphere or None if spheres separate
+fn sphere_impulse(first: &Sphere, second: &Sphere, COR: f32, friction: f32, loop_time: f32) -> Option<(Vector3<f32>,Vector3<f32>,Vector3<f32>)>
+{
+    let inv_mass = first.inv_mass + second.inv_mass;
+    let delta = first.pos - second.pos;
+    let penetration = first.radius + second.radius - delta.norm();
+    if inv_mass <= 0.0 || penetration <= 0.0
+    {
+        return None;
+    }
+    let normal = delta.try_normalize(f32::EPSILON).unwrap_or(Vector3::z());
+    let rel_vel = first.vel - second.vel;
+    let normal_vel = rel_vel.dot(&normal);
+    let separating_vel = (-COR * normal_vel).max(PENETRATION_CORRECTION * penetration / loop_time);
+    if normal_vel >= separating_vel
+    {
+        return None;
+    }
+    let normal_impulse = (separating_vel - normal_vel) / inv_mass;
+    let tangent_vel = rel_vel - normal * normal_vel;
+    let tangent_impulse = match tangent_vel.try_normalize(f32::EPSILON) {
+        Some(tangent) => -tangent * (tangent_vel.norm() / inv_mass).min(friction * normal_impulse),
+        None => Vector3::zeros(),
+    };
+    let point = second.pos + normal * (second.radius - 0.5 * penetration);
+    Some((normal * normal_impulse + tangent_impulse, point, normal))
+}
+
+/// Rigid body in contact point
+struct ContactBody
+{
+    inv_mass: f32,
+    /// inverse inertia tensor in world frame
+    inv_inertia: Matrix3<f32>,
+    /// contact point relative to center of mass in world frame
+    arm: Vector3<f32>,
+    /// velocity of contact point in world frame
+    velocity: Vector3<f32>,
+}
+
+/// Finds the deepest contact between two UAVs. Returns contact point and normal pointing from second to first UAV
+fn drone_contact(first: &DronePose, second: &DronePose) -> Option<(Vector3<f32>,Vector3<f32>)>
+{
+    let inFirst = deepest_vertex(second, first).map(|(point, normal, depth)| (point, -normal, depth));
+    let inSecond = deepest_vertex(first, second);
+    match (inFirst, inSecond) {
+        (Some(a), Some(b)) => Some(if a.2 > b.2 { (a.0, a.1) } else { (b.0, b.1) }),
+        (Some(a), None) | (None, Some(a)) => Some((a.0, a.1)),
+        (None, None) => None,
+    }
+}
+
+/// Finds vertex of body mesh that is the deepest inside hull of other UAV.
+/// Returns vertex, outward normal of the nearest hull face and depth
+fn deepest_vertex(body: &DronePose, hull: &DronePose) -> Option<(Vector3<f32>,Vector3<f32>,f32)>
+{
+    let toHull = hull.rot.transpose();
+    let mut best: Option<(Vector3<f32>,Vector3<f32>,f32)> = None;
+    for col in body.mesh.column_iter()
+    {
+        let point = body.rot * col + body.pos;
+        let local = toHull * (point - hull.pos);
+        let below = local - hull.hull_min;
+        let above = hull.hull_max - local;
+        if below.min() < 0.0 || above.min() < 0.0
+        {
+            continue;
+        }
+        let (depth, normal) = if below.min() < above.min() {
+            (below.min(), -Vector3::ith(below.imin(), 1.0))
+        } else {
+            (above.min(), Vector3::ith(above.imin(), 1.0))
+        };
+        if best.is_none_or(|b| depth > b.2)
+        {
+            best = Some((point, hull.rot * normal, depth));
+        }
+    }
+    best
+}
+
+/// Calculates impulse exchanged in contact of two rigid bodies with coefficient of restitution.
+/// Normal points from second to first body. Returns impulse applied to first body, second body gets the opposite.
+/// Returns zero if bodies are already separating.
+fn contact_impulse(first: &ContactBody, second: &ContactBody, normal: &Vector3<f32>, COR: f32) -> Vector3<f32>
+{
+    let approach = (first.velocity - second.velocity).dot(normal);
+    if approach >= 0.0
+    {
+        return Vector3::zeros();
+    }
+    let angular = |body: &ContactBody| normal.dot(&(body.inv_inertia * body.arm.cross(normal)).cross(&body.arm));
+    let denominator = first.inv_mass + second.inv_mass + angular(first) + angular(second);
+    if denominator <= 0.0
+    {
+        return Vector3::zeros();
+    }
+    normal * (-(1.0 + COR) * approach / denominator)
+}
+
 /// Returns mesh of UAV. Load UAV OBJ file on first call.
 fn getMesh<'a>(meshes: &'a mut HashMap<String, DMatrix<f32>>, drone_type: & str) -> &'a DMatrix<f32> {
     if !meshes.contains_key(drone_type)
     {
-        let drone_model = Obj::load_from_file(format!("./assets/drones/{}/model/model.obj", drone_type).as_str(),true);
+        let drone_model = Obj::load_from_file(format!("./assets/drones/{}/model/model.obj", drone_type).as_str(),true)
+            .unwrap_or_else(|e| {
+                printError!("Can not load UAV model {}. Assumed empty model", e);
+                Obj::default()
+            });
         let mesh = drone_model.getMesh();
         meshes.insert(drone_type.to_string(), mesh);
     }
@@ -330,4 +794,63 @@ mod tests {
         assert!((rot.m32 - 0.3536).abs() < EPS);
         assert!((rot.m21 - 0.0).abs() < EPS);
     }
+
+    #[test]
+    fn drones_exchange_impulse_on_contact() {
+        // Unit cube meshes overlapping along x axis
+        let cube = DMatrix::from_fn(3, 8, |axis, corner| if (corner >> axis) & 1 == 1 { 0.5 } else { -0.5 });
+        let first = DronePose::new(Vector3::new(0.95, 0.2, 0.3), Matrix3::identity(), &cube).unwrap();
+        let second = DronePose::new(Vector3::zeros(), Matrix3::identity(), &cube).unwrap();
+        let (_, normal) = drone_contact(&first, &second).unwrap();
+        assert_eq!(normal, Vector3::new(1.0, 0.0, 0.0));
+        let far = DronePose::new(Vector3::new(1.1, 0.0, 0.0), Matrix3::identity(), &cube).unwrap();
+        assert!(drone_contact(&far, &second).is_none());
+
+        // Head-on elastic collision of equal masses swaps velocities
+        let body = |velocity: f32, inv_mass: f32| ContactBody { inv_mass, inv_inertia: Matrix3::zeros(),
+            arm: Vector3::zeros(), velocity: Vector3::new(velocity, 0.0, 0.0) };
+        let impulse = contact_impulse(&body(-1.0, 1.0), &body(1.0, 1.0), &normal, 1.0);
+        assert!((impulse - Vector3::new(2.0, 0.0, 0.0)).norm() < EPS);
+        // Heavy second body and no restitution stops the first one
+        let impulse = contact_impulse(&body(-1.0, 1.0), &body(0.0, 0.0), &normal, 0.0);
+        assert!((impulse - Vector3::new(1.0, 0.0, 0.0)).norm() < EPS);
+        assert_eq!(contact_impulse(&body(1.0, 1.0), &body(0.0, 1.0), &normal, 1.0), Vector3::zeros());
+    }
+
+    #[test]
+    fn objects_collide_as_spheres() {
+        let objs = vec![(1, Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), 0.5),
+            (2, Vector3::new(0.9, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0), 0.5),
+            (3, Vector3::new(0.0, 1.1, 0.0), Vector3::zeros(), 0.5),
+            (4, Vector3::new(50.0, 0.0, 0.0), Vector3::zeros(), 0.1)];
+        assert_eq!(overlapping_spheres(&objs), vec![(0, 1)]);
+
+        // Head-on elastic collision of equal masses swaps velocities
+        let sphere = |obj: &(usize,Vector3<f32>,Vector3<f32>,f32)| Sphere { pos: obj.1, vel: obj.2, radius: obj.3, inv_mass: 1.0 };
+        let (impulse, point, normal) = sphere_impulse(&sphere(&objs[0]), &sphere(&objs[1]), 1.0, 0.0, 0.1).unwrap();
+        assert!((impulse - Vector3::new(-2.0, 0.0, 0.0)).norm() < EPS);
+        assert!((point - Vector3::new(0.45, 0.0, 0.0)).norm() < EPS);
+        assert_eq!(normal, Vector3::new(-1.0, 0.0, 0.0));
+
+        // Sliding sphere falling on heavy one bounces out of penetration and is slowed down by friction limited by normal impulse
+        let top = Sphere { pos: Vector3::new(0.0, 0.0, -0.95), vel: Vector3::new(2.0, 0.0, 1.0), radius: 0.5, inv_mass: 1.0 };
+        let bottom = Sphere { pos: Vector3::zeros(), vel: Vector3::zeros(), radius: 0.5, inv_mass: 0.0 };
+        let (impulse, _, _) = sphere_impulse(&top, &bottom, 0.0, 0.5, 0.01).unwrap();
+        assert!((impulse - Vector3::new(-1.0, 0.0, -2.0)).norm() < EPS);
+        let separating = Sphere { vel: Vector3::new(0.0, 0.0, -10.0), ..top };
+        assert!(sphere_impulse(&separating, &bottom, 0.0, 0.5, 0.01).is_none());
+    }
+
+    #[test]
+    fn object_hits_drone_hull() {
+        let cube = DMatrix::from_fn(3, 8, |axis, corner| if (corner >> axis) & 1 == 1 { 0.5 } else { -0.5 });
+        let yaw = Matrix3::new(0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0);
+        let pose = DronePose::new(Vector3::new(10.0, 0.0, 0.0), yaw, &cube).unwrap();
+        let point = object_hit(&pose, &Vector3::new(8.0, 0.2, 0.0), &Vector3::new(100.0, 0.0, 0.0), 0.1, 0.1).unwrap();
+        assert!((point - Vector3::new(9.4, 0.2, 0.0)).norm() < EPS);
+        // Too short step, moving away and passing by
+        assert!(object_hit(&pose, &Vector3::new(8.0, 0.2, 0.0), &Vector3::new(100.0, 0.0, 0.0), 0.1, 0.01).is_none());
+        assert!(object_hit(&pose, &Vector3::new(8.0, 0.2, 0.0), &Vector3::new(-100.0, 0.0, 0.0), 0.1, 0.1).is_none());
+        assert!(object_hit(&pose, &Vector3::new(8.0, 0.7, 0.0), &Vector3::new(100.0, 0.0, 0.0), 0.1, 0.1).is_none());
+    }
 }
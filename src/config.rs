@@ -1,10 +1,12 @@
 use std::f32::consts::PI;
+use std::collections::HashMap;
 use std::sync::atomic::{self, AtomicBool};
 use std::{fs::File, sync::Mutex};
 use std::io::Read;
-use nalgebra::{DMatrix,Vector3};
+use nalgebra::{DMatrix,Matrix3,Vector3};
 use xmltree::Element;
 use crate::obj::Obj;
+use crate::printError;
 
 /// Path to aggregator configuration YAML file
 const CONFIG_FILE_PATH: &str = "configs/config.yaml";
@@ -77,6 +79,32 @@ impl ServerConfig {
         let config_data = config_lck.clone().unwrap();
         config_data[key].as_bool().unwrap()
     }
+
+    /// Gets parameter with specified name (key) if it is unsigned number. Returns None otherwise
+    pub fn get_opt_u64(key :&str) -> Option<u64>
+    {
+        if !READY.load(atomic::Ordering::Relaxed)
+        {
+            Self::readConfig();
+        }
+        let config_lck = CONFIG.lock().unwrap();
+        let config_data = config_lck.clone().unwrap();
+        config_data[key].as_u64()
+    }
+
+    /// Gets parameter with specified name (key) and try to parse it to map of strings
+    pub fn get_str_map(key :&str) -> HashMap<String,String>
+    {
+        if !READY.load(atomic::Ordering::Relaxed)
+        {
+            Self::readConfig();
+        }
+        let config_lck = CONFIG.lock().unwrap();
+        let config_data = config_lck.clone().unwrap();
+        config_data[key].as_mapping().unwrap().iter()
+            .map(|(k, v)| (k.as_str().unwrap().to_owned(), v.as_str().unwrap().to_owned()))
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -93,6 +121,8 @@ pub struct AmmoParams {
     pub C0: f32,
     pub mass: f32,
     pub ammount: usize,
+    /// marker ammunition only marks hit aircraft without damaging it
+    pub marker: bool,
 
     pub CS: f32,
 }
@@ -132,6 +162,7 @@ fn parse_ammo(element: &Element) -> AmmoParams {
     let C0 = element.get_child("C0").unwrap().get_text().unwrap().parse().unwrap();
     let mass = element.get_child("mass").unwrap().get_text().unwrap().parse().unwrap();
     let ammount = element.get_child("ammount").unwrap().get_text().unwrap().parse().unwrap();
+    let marker = element.get_child("marker").is_some_and(|m| m.get_text().unwrap().trim() == "true");
 
     AmmoParams {
         name,
@@ -143,6 +174,7 @@ fn parse_ammo(element: &Element) -> AmmoParams {
         C0,
         mass,
         ammount,
+        marker,
         CS: C0 * PI * radius* radius
     }
 }
@@ -179,6 +211,27 @@ fn parse_cargo(element: &Element) -> CargoParams {
     }
 }
 
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+/// Parsed damage model parameters
+pub struct DamageParams {
+    /// hit points of undamaged aircraft
+    pub hit_points: f32,
+    /// hit points lost per joule of impact kinetic energy
+    pub energy_scale: f32,
+}
+
+/// Parses damage model parameters
+fn parse_damage(element: &Element) -> Result<DamageParams, Box<dyn std::error::Error>> {
+    let param = |name: &str| -> Result<f32, Box<dyn std::error::Error>> {
+        Ok(element.get_child(name).and_then(|n| n.get_text()).ok_or(format!("Missing {} in damage node", name))?.trim().parse()?)
+    };
+    Ok(DamageParams {
+        hit_points: param("hitPoints")?,
+        energy_scale: param("energyScale")?,
+    })
+}
+
 /// Configuration of UAV
 #[derive(Clone)]
 pub struct DroneConfig {
@@ -186,7 +239,13 @@ pub struct DroneConfig {
     pub drone_type: String,
     pub cargo: Vec<CargoParams>,
     pub ammo: Vec<AmmoParams>,
-    pub mesh: DMatrix<f32>
+    pub mesh: DMatrix<f32>,
+    /// mass in kg
+    pub mass: f32,
+    /// inertia tensor in body frame in kg*m2
+    pub inertia: Matrix3<f32>,
+    /// damage model, aircraft without it can not be damaged
+    pub damage: Option<DamageParams>,
 }
 
 impl DroneConfig {
@@ -215,7 +274,28 @@ impl DroneConfig {
                 .map(|child| parse_ammo(child.as_element().unwrap())).collect();
         } 
 
-        let drone_model = Obj::load_from_file(format!("./assets/drones/{}/model/model.obj", &drone_type.as_str()).as_str(),true);
+        let inertia_node = root.get_child("ineria").ok_or("Missing ineria node")?;
+        let inertia_param = |name: &str| -> Result<f32, Box<dyn std::error::Error>> {
+            Ok(inertia_node.get_child(name).and_then(|n| n.get_text()).ok_or(format!("Missing {} in ineria node", name))?.trim().parse()?)
+        };
+        let mass = inertia_param("mass")?;
+        let (Ixy, Ixz, Iyz) = (inertia_param("Ixy")?, inertia_param("Ixz")?, inertia_param("Iyz")?);
+        let inertia = Matrix3::new(
+            inertia_param("Ix")?, -Ixy, -Ixz,
+            -Ixy, inertia_param("Iy")?, -Iyz,
+            -Ixz, -Iyz, inertia_param("Iz")?);
+
+        let damage = match root.get_child("damage")
+        {
+            Some(damage_node) => Some(parse_damage(damage_node)?),
+            None => None
+        };
+
+        let drone_model = Obj::load_from_file(format!("./assets/drones/{}/model/model.obj", &drone_type.as_str()).as_str(),true)
+            .unwrap_or_else(|e| {
+                printError!("Can not load UAV model {}. Assumed empty model", e);
+                Obj::default()
+            });
         let mesh = drone_model.getMesh();
 
         let config = DroneConfig {
@@ -223,7 +303,10 @@ impl DroneConfig {
             drone_type,
             cargo,
             ammo,
-            mesh
+            mesh,
+            mass,
+            inertia,
+            damage,
         };
 
         Ok(config)
@@ -1,9 +1,10 @@
-use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread::{JoinHandle, self}, time::{self, Instant}};
-use nalgebra::{Vector3,Vector4, DVector};
-use crate::{uav::{UAV,DroneState}, notification::{Notification, PromptColor, PromptCategory}, atmosphere::GRAVITY_ACCELERATION};
+use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread::{JoinHandle, self}, time::{self, Instant}, collections::HashMap};
+use nalgebra::{Vector3,Vector4, DVector, Matrix3};
+use crate::{uav::{UAV,DroneState,UavControl}, notification::{Notification, PromptColor, PromptCategory}, atmosphere::GRAVITY_ACCELERATION, simulation::Simulation};
 use crate::objects::Objects;
+use crate::forces::OuterForces;
 use crate::config::ServerConfig;
-use crate::printLog;
+use crate::{printLog, printWarn};
 
 /// Control all UAVs in air. Communicate with simulation processes and visualizations
 pub struct Drones
@@ -14,7 +15,11 @@ pub struct Drones
     objects: Arc<Mutex<Objects>>,
     _state_publisher: Option<thread::JoinHandle<()>>,
     nextID: usize,
-    slots: DVector<usize>
+    slots: DVector<usize>,
+    /// outer forces set by cargo links and collision impulses per UAV
+    forces: OuterForces,
+    /// hit points left of damaged UAVs
+    hit_points: HashMap<usize,f32>,
 }
 
 impl Drones
@@ -25,6 +30,7 @@ impl Drones
         let client_limit: usize = ServerConfig::get_usize("client_limit");
         let mut last_notify = Instant::now();
         let notify_period = ServerConfig::get_usize("notify_period").try_into().unwrap();
+        let clock_drift_warn = ServerConfig::get_f32("clock_drift_warn");
         let running = Arc::new(AtomicBool::new(true));
         let r = running.clone();
         let drones = Arc::new(Mutex::new(Vec::<UAV>::new()));
@@ -37,14 +43,18 @@ impl Drones
             printLog!("State publisher started on TCP: {}", port);
             while r.load(Ordering::SeqCst) {
                 let drones = drones_arc.lock().unwrap();
+                let mut timeToNotify = false;
+                if last_notify.elapsed().as_millis() > notify_period
+                {
+                    last_notify = Instant::now();
+                    timeToNotify = true;
+                    Notification::sendMsg(&Simulation::clockMsg());
+                }
                 if !drones.is_empty()
                 {
-                    let mut timeToNotify = false;
                     let mut notifyTypesMsg = String::new();
-                    if last_notify.elapsed().as_millis() > notify_period
+                    if timeToNotify
                     {
-                        last_notify = Instant::now();
-                        timeToNotify = true;
                         notifyTypesMsg.reserve(drones.len()*50);
                         notifyTypesMsg.push_str("t:");
                     }
@@ -58,6 +68,11 @@ impl Drones
                         if timeToNotify
                         {
                             check_acceleration(elem.id, state.getAcc(), notify_period);
+                            let lag = state.getClockLag(Simulation::now());
+                            if lag > clock_drift_warn
+                            {
+                                printWarn!("Drone {}: simulation time lags master clock by {:.3} s", elem.id, lag);
+                            }
                         }
                         drop(state);
                         result.push(';');
@@ -84,7 +99,8 @@ impl Drones
             }
         });
         Drones {ctx: _ctx, running: running, drones: drones, objects: objects,
-             _state_publisher: Some(publisher), nextID: 1, slots }
+             _state_publisher: Some(publisher), nextID: 1, slots,
+             forces: OuterForces::default(), hit_points: HashMap::new() }
     }
 
 
@@ -138,15 +154,51 @@ impl Drones
         let mut drone = self.drones.lock().unwrap();
         let id = self.nextID;
         self.nextID += 1;
-        drone.push(UAV::new(&mut self.ctx,id, name, config_path, state,self.objects.clone()));
+        let uav = UAV::new(&mut self.ctx,id, name, config_path, state,self.objects.clone());
+        if Simulation::isPaused() || Simulation::getTimeScale() != 1.0
+        {
+            uav.sendSimulationState(&Simulation::stateMsg());
+        }
+        drone.push(uav);
         drop(drone);
         (id,slot,format!("ipc:///tmp/{}/steer", name))
     }
 
+    /// Sends current simulation pause and time scale to all UAVs and objects simulation
+    pub fn sendSimulationState(&self)
+    {
+        let msg = Simulation::stateMsg();
+        let drone_lck = self.drones.lock().unwrap();
+        for uav in drone_lck.iter()
+        {
+            uav.sendSimulationState(&msg);
+        }
+        drop(drone_lck);
+        let objects_lck = self.objects.lock().unwrap();
+        objects_lck.sendSimulationState(&msg);
+        drop(objects_lck);
+    }
+
+    /// Sends lockstep step command to all UAVs and objects simulation
+    pub fn sendStep(&self, step_ms: usize)
+    {
+        let drone_lck = self.drones.lock().unwrap();
+        for uav in drone_lck.iter()
+        {
+            uav.sendStep(step_ms);
+        }
+        drop(drone_lck);
+        let objects_lck = self.objects.lock().unwrap();
+        objects_lck.sendStep(step_ms);
+        drop(objects_lck);
+    }
+
     /// Remove UAV specified by id
     pub fn removeUAV(&mut self, id: usize)
     {
         self.freeSlot(id);
+        self.forces.remove(id);
+        self.hit_points.remove(&id);
         let mut drone = self.drones.lock().unwrap();
         drone.retain_mut(|d| d.id != id);
         drop(drone);
@@ -236,17 +288,73 @@ impl Drones
         types
     }
 
-    /// Update outer force for UAV specified by id
-    pub fn updateForce(&self, id: &usize, force: &Vector3<f32>, torque: &Vector3<f32>)
+    /// Get handles to control channels of active UAVs
+    pub fn getControls(&self) -> Vec<UavControl>
     {
+        let drone = self.drones.lock().unwrap();
+        let controls = drone.iter().map(|elem| elem.control()).collect();
+        drop(drone);
+        controls
+    }
+
+    /// Get mass and inertia tensor in body frame of active UAVs
+    pub fn getBodies(&self) -> Vec<(usize,f32,Matrix3<f32>)>
+    {
+        let drone = self.drones.lock().unwrap();
+        let bodies = drone.iter().map(|elem| (elem.id, elem.config.mass, elem.config.inertia)).collect();
+        drop(drone);
+        bodies
+    }
+
+    /// Update outer force for UAV specified by id. Active collision impulses are added to it
+    pub fn updateForce(&mut self, id: &usize, force: &Vector3<f32>, torque: &Vector3<f32>)
+    {
+        self.forces.set(*id, force, torque);
+        self.sendForce(id);
+    }
+
+    /// Applies impulse and angular impulse to UAV specified by id. Impulse is sent as constant force
+    /// lasting duration in s of simulation time, added to outer force
+    pub fn applyImpulse(&mut self, id: &usize, impulse: &Vector3<f32>, angular_impulse: &Vector3<f32>, duration: f32)
+    {
+        self.forces.addImpulse(*id, impulse, angular_impulse, duration);
+        self.sendForce(id);
+    }
+
+    /// Removes finished collision impulses and restores outer force of their UAVs
+    pub fn expireImpulses(&mut self)
+    {
+        for id in self.forces.expire()
+        {
+            self.sendForce(&id);
+        }
+    }
+
+    /// Sends sum of outer force and active impulse force to UAV specified by id
+    fn sendForce(&self, id: &usize)
+    {
+        let (force, torque) = self.forces.total(*id);
         let drone_lck = self.drones.lock().unwrap();
         if let Some(uav) = drone_lck.iter().find(|uav| uav.id == *id)
         {
-            uav.updateForce(force,torque);
+            uav.updateForce(&force,&torque);
         }
         drop(drone_lck);
     }
 
+    /// Reduces hit points of UAV specified by id according to its damage model and impact energy in J.
+    /// Returns damage and hit points left or None if UAV can not be damaged
+    pub fn applyDamage(&mut self, id: &usize, energy: f32) -> Option<(f32,f32)>
+    {
+        let drone_lck = self.drones.lock().unwrap();
+        let damage_params = drone_lck.iter().find(|uav| uav.id == *id)?.config.damage?;
+        drop(drone_lck);
+        let damage = energy * damage_params.energy_scale;
+        let hit_points = self.hit_points.entry(*id).or_insert(damage_params.hit_points);
+        *hit_points -= damage;
+        Some((damage, *hit_points))
+    }
+
     /// Sends information about collsion with surface to UAV specified by id
     pub fn sendSurfaceCollison(&self, id: &usize, COR: f32, mi_s: f32, mi_d: f32, collisionPoint: &Vector3<f32>, normalVector: &Vector3<f32>)
     {
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use nalgebra::Vector3;
+use crate::simulation::Simulation;
+
+/// Impulse ending within this time in s is treated as finished. Protects against rounding of master clock steps
+const EXPIRY_TOLERANCE: f32 = 1e-4;
+
+/// Collision impulse spread over time as constant force and torque
+struct Impulse
+{
+    force: Vector3<f32>,
+    torque: Vector3<f32>,
+    /// master clock time when impulse ends
+    end: f32,
+}
+
+/// Outer forces and torques applied to simulated bodies: force set by links and collision impulses spread over time.
+/// Owner sends total value to body process whenever it changes
+#[derive(Default)]
+pub struct OuterForces
+{
+    /// last outer force and torque set by links per body
+    outer: HashMap<usize,(Vector3<f32>,Vector3<f32>)>,
+    /// active collision impulses per body
+    impulses: HashMap<usize,Vec<Impulse>>,
+}
+
+impl OuterForces
+{
+    /// Sets outer force and torque of body specified by id
+    pub fn set(&mut self, id: usize, force: &Vector3<f32>, torque: &Vector3<f32>)
+    {
+        self.outer.insert(id, (*force, *torque));
+    }
+
+    /// Adds impulse and angular impulse to body specified by id as constant force lasting duration in s of simulation time.
+    /// Impulses overlapping in time are summed, each one ends on its own
+    pub fn addImpulse(&mut self, id: usize, impulse: &Vector3<f32>, angular_impulse: &Vector3<f32>, duration: f32)
+    {
+        self.impulses.entry(id).or_default()
+            .push(Impulse { force: impulse / duration, torque: angular_impulse / duration, end: Simulation::now() + duration });
+    }
+
+    /// Removes finished impulses. Returns ids of bodies which total force changed
+    pub fn expire(&mut self) -> Vec<usize>
+    {
+        let now = Simulation::now();
+        let mut changed = Vec::new();
+        for (id, impulses) in self.impulses.iter_mut()
+        {
+            let count = impulses.len();
+            impulses.retain(|impulse| impulse.end > now + EXPIRY_TOLERANCE);
+            if impulses.len() != count
+            {
+                changed.push(*id);
+            }
+        }
+        self.impulses.retain(|_, impulses| !impulses.is_empty());
+        changed
+    }
+
+    /// Returns sum of outer force and active impulse force and torque of body specified by id
+    pub fn total(&self, id: usize) -> (Vector3<f32>,Vector3<f32>)
+    {
+        let (mut force, mut torque) = self.outer.get(&id).copied().unwrap_or((Vector3::zeros(), Vector3::zeros()));
+        for impulse in self.impulses.get(&id).into_iter().flatten()
+        {
+            force += impulse.force;
+            torque += impulse.torque;
+        }
+        (force, torque)
+    }
+
+    /// Forgets forces of removed body
+    pub fn remove(&mut self, id: usize)
+    {
+        self.outer.remove(&id);
+        self.impulses.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulses_end_independently() {
+        let mut forces = OuterForces::default();
+        forces.set(1, &Vector3::new(1.0, 0.0, 0.0), &Vector3::zeros());
+        forces.addImpulse(1, &Vector3::new(0.0, 10.0, 0.0), &Vector3::zeros(), 1000.0);
+        forces.addImpulse(1, &Vector3::new(0.0, 0.0, 1e-5), &Vector3::new(1e-5, 0.0, 0.0), 1e-5);
+        assert_eq!(forces.total(1), (Vector3::new(1.0, 0.01, 1.0), Vector3::new(1.0, 0.0, 0.0)));
+        // Only the short impulse ends, the long one is not extended nor doubled
+        assert_eq!(forces.expire(), vec![1]);
+        assert_eq!(forces.total(1), (Vector3::new(1.0, 0.01, 0.0), Vector3::zeros()));
+        assert!(forces.expire().is_empty());
+        forces.remove(1);
+        assert_eq!(forces.total(1), (Vector3::zeros(), Vector3::zeros()));
+    }
+}
@@ -0,0 +1,109 @@
+use std::{thread::{JoinHandle, self}, sync::{Mutex, Arc, atomic::{AtomicBool, Ordering}}, time::{self, Instant}};
+use crate::{drones::Drones, objects::Objects, config::ServerConfig, simulation::{Simulation, StepPhase}};
+use crate::{printLog, printError};
+
+/// How long stepper waits for child simulations to finish the step before reporting them
+const STEP_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// Deterministic lockstep driver. Instead of free running, child simulations are advanced
+/// by fixed steps on request. After each step collision, cargo and atmosphere are run once
+/// in this order and master clock is advanced by the step.
+pub struct Lockstep
+{
+    running: Arc<AtomicBool>,
+    stepper: Option<thread::JoinHandle<()>>
+}
+
+impl Lockstep
+{
+    /// Constructor. Starts stepping thread
+    pub fn new(drones: Arc<Mutex<Drones>>, objects: Arc<Mutex<Objects>>) -> Self
+    {
+        let step_ms = ServerConfig::get_usize("lockstep_step_time");
+        let step = step_ms as f32 / 1000.0;
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let stepper: JoinHandle<()> = thread::spawn(move ||
+        {
+            printLog!("Lockstep started with step {} ms", step_ms);
+            while r.load(Ordering::SeqCst) {
+                let start = Instant::now();
+                if Simulation::isPaused()
+                {
+                    thread::sleep(time::Duration::from_millis(step_ms as u64));
+                    continue;
+                }
+                let mut before = childrenTimes(&drones, &objects);
+                let drones_lck = drones.lock().unwrap();
+                drones_lck.sendStep(step_ms);
+                drop(drones_lck);
+
+                // Wait until every child simulation reports state after the step.
+                // Step never proceeds without a child, only removed children stop being waited for
+                let mut reported = false;
+                while r.load(Ordering::SeqCst)
+                {
+                    let after = childrenTimes(&drones, &objects);
+                    before.retain(|(id, time)|
+                        after.iter().any(|(id2, time2)| id == id2 && time2 - time < step * 0.999));
+                    if before.is_empty()
+                    {
+                        break;
+                    }
+                    if !reported && start.elapsed() > STEP_TIMEOUT
+                    {
+                        let ids: Vec<usize> = before.iter().map(|(id, _)| *id).collect();
+                        printError!("Lockstep: child simulations {:?} did not finish step in {:?}, still waiting", ids, STEP_TIMEOUT);
+                        reported = true;
+                    }
+                    thread::sleep(time::Duration::from_micros(200));
+                }
+                if !r.load(Ordering::SeqCst)
+                {
+                    break;
+                }
+
+                Simulation::advance(step);
+                Simulation::runPhase(StepPhase::COLLISION, &r);
+                Simulation::runPhase(StepPhase::CARGO, &r);
+                Simulation::runPhase(StepPhase::ATMOSPHERE, &r);
+
+                // Keep pace of time scale
+                let period = time::Duration::from_secs_f32(step / Simulation::getTimeScale());
+                let elapsed = start.elapsed();
+                if elapsed < period
+                {
+                    thread::sleep(period - elapsed);
+                }
+            }
+        });
+        Lockstep { running, stepper: Some(stepper) }
+    }
+}
+
+/// Returns simulation time of every drone and of objects simulation. Objects have id 0
+fn childrenTimes(drones: &Arc<Mutex<Drones>>, objects: &Arc<Mutex<Objects>>) -> Vec<(usize, f32)>
+{
+    let drones_lck = drones.lock().unwrap();
+    let mut times: Vec<(usize, f32)> = drones_lck.drones.lock().unwrap().iter()
+        .map(|uav| (uav.id, uav.state_arc.lock().unwrap().getTime()))
+        .collect();
+    drop(drones_lck);
+    let objects_lck = objects.lock().unwrap();
+    if !objects_lck.states.lock().unwrap().is_empty()
+    {
+        times.push((0, *objects_lck._time.lock().unwrap()));
+    }
+    drop(objects_lck);
+    times
+}
+
+/// Deconstructor
+impl Drop for Lockstep{
+    fn drop(&mut self) {
+        printLog!("Dropping lockstep instance");
+        self.running.store(false, Ordering::SeqCst);
+        self.stepper.take().unwrap().join().expect("Join error");
+        printLog!("Lockstep instance dropped");
+    }
+}
@@ -1,17 +1,31 @@
-use std::fs::{File,remove_file,create_dir};
-use std::io::Write;
+use std::collections::HashMap;
+use std::fs::{File,remove_file,create_dir,read_dir,read_to_string,rename,copy,remove_dir_all,write};
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::path::Path;
+use std::process::Command;
 use std::sync::Mutex;
-use std::time::{SystemTime,UNIX_EPOCH};
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
 use std::time::Instant;
+use serde_json::{json, Value};
+use crate::config::ServerConfig;
+use crate::simulation::Simulation;
 
 /// session identificator
 static SESSION: Mutex<String> = Mutex::new(String::new());
-/// File descriptor to log file
-pub static LOG_FILE: Mutex<Option<File>> = Mutex::new(Option::None);
+/// Opened log file
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(Option::None);
 /// Path to folder where logs are stored
 const LOG_FOLDER: &str = "./logs/";
+/// Path to marker file containing name of running session. Read by simulation processes
+const SESSION_MARKER_PATH: &str = "./logs/session";
+/// Name of session metadata file in session folder
+const SESSION_METADATA_FILE: &str = "session.json";
+/// Path to aggregator configuration copied to session folder
+const CONFIG_FILE_PATH: &str = "configs/config.yaml";
 /// Start of application - timestamp
 pub static START_TIME: Mutex<Option<Instant>> = Mutex::new(None);
+/// Log filtering and formatting settings. Loaded from configuration on first use
+static SETTINGS: Mutex<Option<LogSettings>> = Mutex::new(None);
 
 /// Changes color of UNIX terminal to BLACK
 pub const COLOR_NORMAL: &str =  "\x1B[0m";
@@ -30,38 +44,207 @@ pub const COLOR_CYAN: &str = "\x1B[36m";
 /// Changes color of UNIX terminal to WHITE
 pub const COLOR_WHITE: &str = "\x1B[37m";
 
-/// Print wrapper that also log output to file
+/// Print wrapper that also log output to file. Message has INFO level
 #[macro_export]
 macro_rules! printLog
 {
-    () => {
-        println("\n")
+    ($($arg:tt)*) => {
+        $crate::logger::Logger::server($crate::logger::LogLevel::INFO, || format!($($arg)*))
     };
-    ($($arg:tt)*) => {{
-        let time = crate::logger::START_TIME.lock().unwrap(); 
-        let time_elapsed = time.unwrap().elapsed().as_secs_f32();
-        drop(time);
-        print!("{}{:9.3} {}[Server] ", crate::logger::COLOR_WHITE, time_elapsed, crate::logger::COLOR_YELLOW);
-        println!($($arg)*);
-        let mut log_file = crate::logger::LOG_FILE.lock().unwrap();
-        if let Some(file) = log_file.as_mut()
+}
+
+/// Print wrapper that also log output to file. Message has ERROR level
+#[macro_export]
+macro_rules! printError
+{
+    ($($arg:tt)*) => {
+        $crate::logger::Logger::server($crate::logger::LogLevel::ERROR, || format!($($arg)*))
+    };
+}
+
+/// Print wrapper that also log output to file. Message has WARN level
+#[macro_export]
+macro_rules! printWarn
+{
+    ($($arg:tt)*) => {
+        $crate::logger::Logger::server($crate::logger::LogLevel::WARN, || format!($($arg)*))
+    };
+}
+
+/// Print wrapper that also log output to file. Message has DEBUG level
+#[macro_export]
+macro_rules! printDebug
+{
+    ($($arg:tt)*) => {
+        $crate::logger::Logger::server($crate::logger::LogLevel::DEBUG, || format!($($arg)*))
+    };
+}
+
+/// Print wrapper that also log output to file. Message has TRACE level
+#[macro_export]
+macro_rules! printTrace
+{
+    ($($arg:tt)*) => {
+        $crate::logger::Logger::server($crate::logger::LogLevel::TRACE, || format!($($arg)*))
+    };
+}
+
+/// Importance of logged message. Lower value is more important
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel
+{
+    ERROR = 0,
+    WARN = 1,
+    INFO = 2,
+    DEBUG = 3,
+    TRACE = 4,
+}
+
+impl LogLevel {
+    /// Parses level name used in configuration file
+    pub fn parse(name: &str) -> Option<Self>
+    {
+        match name.trim().to_lowercase().as_str() {
+            "error" => Some(LogLevel::ERROR),
+            "warn"  => Some(LogLevel::WARN),
+            "info"  => Some(LogLevel::INFO),
+            "debug" => Some(LogLevel::DEBUG),
+            "trace" => Some(LogLevel::TRACE),
+            _       => None,
+        }
+    }
+
+    /// Returns level name as used in configuration file
+    pub fn to_str(&self) -> &'static str
+    {
+        match self {
+            LogLevel::ERROR => "error",
+            LogLevel::WARN  => "warn",
+            LogLevel::INFO  => "info",
+            LogLevel::DEBUG => "debug",
+            LogLevel::TRACE => "trace",
+        }
+    }
+}
+
+/// Log filtering and formatting settings
+struct LogSettings
+{
+    /// level used when no override matches
+    level: LogLevel,
+    /// level overrides per source: server, sim, ctrl, drop or <drone name>/sim, <drone name>/ctrl
+    overrides: HashMap<String, LogLevel>,
+    /// write log file as JSON lines instead of plain text
+    json: bool,
+    /// print ANSI colors to terminal
+    colors: bool,
+    /// rotate log file when it exceeds this size in bytes. Zero disables
+    rotate_size: u64,
+    /// rotate log file when it is open longer than this period. Zero disables
+    rotate_period: Duration,
+}
+
+impl LogSettings {
+    /// Reads settings from configuration file
+    fn fromConfig() -> Self
+    {
+        let level = LogLevel::parse(&ServerConfig::get_str("log_level")).expect("Unknown log level");
+        let overrides = ServerConfig::get_str_map("log_levels").into_iter()
+            .map(|(source, level)| (source.to_lowercase(), LogLevel::parse(&level).expect("Unknown log level")))
+            .collect();
+        let json = match ServerConfig::get_str("log_format").as_str() {
+            "text" => false,
+            "json" => true,
+            other => panic!("Unknown log format: {}", other),
+        };
+        let colors = match ServerConfig::get_str("log_colors").as_str() {
+            "auto" => std::io::stdout().is_terminal(),
+            "always" => true,
+            "never" => false,
+            other => panic!("Unknown log colors mode: {}", other),
+        };
+        let rotate_size = (ServerConfig::get_f32("log_rotate_size_mb") * 1024.0 * 1024.0) as u64;
+        let rotate_period = Duration::from_secs(ServerConfig::get_usize("log_rotate_period") as u64);
+        LogSettings { level, overrides, json, colors, rotate_size, rotate_period }
+    }
+
+    /// Checks if message with specified level from specified source should be logged.
+    /// The most specific override wins: <name>/<source>, then <source>, then <name>.
+    fn enabled(&self, level: LogLevel, name: &str, source: Option<&str>) -> bool
+    {
+        let name = name.to_lowercase();
+        let threshold = match source {
+            Some(source) => {
+                let source = source.to_lowercase();
+                self.overrides.get(&format!("{}/{}", name, source))
+                    .or_else(|| self.overrides.get(&source))
+                    .or_else(|| self.overrides.get(&name))
+            }
+            None => self.overrides.get(&name),
+        };
+        level <= *threshold.unwrap_or(&self.level)
+    }
+}
+
+/// Server log file with rotation bookkeeping
+struct LogFile
+{
+    file: File,
+    /// session folder with trailing slash
+    folder: String,
+    /// bytes written since file was opened
+    written: u64,
+    /// time when file was opened
+    opened: Instant,
+    /// number of already rotated files
+    rotations: usize,
+}
+
+impl LogFile {
+    /// Creates server.log in specified session folder
+    fn create(folder: &str) -> Self
+    {
+        let file = File::create(folder.to_string() + "server.log").expect("Unable to create log file");
+        LogFile { file, folder: folder.to_string(), written: 0, opened: Instant::now(), rotations: 0 }
+    }
+
+    /// Writes line to file
+    fn writeLine(&mut self, line: &str)
+    {
+        self.file.write_all(line.as_bytes()).expect("Unable to write log");
+        self.file.write_all(b"\n").expect("Unable to write log");
+        self.written += line.len() as u64 + 1;
+    }
+
+    /// Moves server.log to server.<n>.log and opens new server.log if size or time limit is reached
+    fn rotateIfNeeded(&mut self, rotate_size: u64, rotate_period: Duration)
+    {
+        let size_exceeded = rotate_size > 0 && self.written >= rotate_size;
+        let time_exceeded = !rotate_period.is_zero() && self.opened.elapsed() >= rotate_period;
+        if !size_exceeded && !time_exceeded
         {
-            std::io::Write::write(file,format!("{:9.3} [Server] ",time_elapsed).as_bytes()).unwrap();
-            std::io::Write::write(file,format!($($arg)*).as_bytes()).expect("Unable to write log");
-            std::io::Write::write(file,b"\n").unwrap();
+            return;
         }
-    }};
+        self.file.flush().expect("Unable to flush log");
+        self.rotations += 1;
+        rename(self.folder.clone() + "server.log", format!("{}server.{}.log", self.folder, self.rotations))
+            .expect("Unable to rotate log file");
+        let rotations = self.rotations;
+        *self = LogFile::create(&self.folder);
+        self.rotations = rotations;
+    }
 }
+
 /// Logger. Used to log execution of program
 pub struct Logger
 {}
 
 impl Logger
 {
-    /// Initialization
-    fn init()
+    /// Initialization. Session specified by keep is never removed by retention
+    fn init(keep: Option<&str>)
     {
-        let mut time = START_TIME.lock().unwrap(); 
+        let mut time = START_TIME.lock().unwrap();
         *time = Some(Instant::now());
         drop(time);
         let session = SESSION.lock().unwrap();
@@ -71,29 +254,145 @@ impl Logger
         }
         drop(session);
         let session = Self::determinateSessionName();
-        let mut file = File::create("./logs/session").unwrap();
-        file.write_all(session.as_bytes()).expect("Unable to write session name.");
-        drop(file);
-        create_dir(LOG_FOLDER.to_string() + session.as_str()).expect("Unable to create session log folder");
+        let folder = LOG_FOLDER.to_string() + session.as_str() + "/";
         let mut log_file = LOG_FILE.lock().unwrap();
-        *log_file = Some(File::create("./logs/".to_string() + session.as_str() + "/server.log").expect("Unable to create log file"));
+        *log_file = Some(LogFile::create(&folder));
         drop(log_file);
+        Self::markCrashedSession();
+        write(SESSION_MARKER_PATH, session.as_bytes()).expect("Unable to write session name.");
+        Self::writeSessionMetadata(&session, &folder);
         printLog!("UAV SERVER");
         printLog!("Session: {}",session);
+        Self::removeOldSessions(&session, keep);
     }
 
-    /// Get session identifier
+    /// Get session identifier. Creates session folder. When folder with the same name exists
+    /// (two runs started in the same second) numeric suffix is added.
     fn determinateSessionName() -> String
     {
         let mut session = SESSION.lock().unwrap();
-        *session = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
+        let base = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string();
+        let mut candidate = base.clone();
+        let mut suffix = 0;
+        loop {
+            match create_dir(LOG_FOLDER.to_string() + candidate.as_str()) {
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    suffix += 1;
+                    candidate = format!("{}_{}", base, suffix);
+                }
+                Err(e) => panic!("Unable to create session log folder: {}", e),
+            }
+        }
+        *session = candidate;
         session.to_string()
     }
 
-    /// Starts log session
-    pub fn startSession()
+    /// Returns path to folder of current session with trailing slash. Empty if session is not started
+    pub fn sessionFolder() -> String
+    {
+        let session = SESSION.lock().unwrap();
+        if session.is_empty()
+        {
+            return String::new();
+        }
+        LOG_FOLDER.to_string() + session.as_str() + "/"
+    }
+
+    /// Writes session metadata and configuration snapshot to session folder
+    fn writeSessionMetadata(session: &str, folder: &str)
+    {
+        copy(CONFIG_FILE_PATH, folder.to_string() + "config.yaml").expect("Unable to copy configuration");
+        let git_hash = Command::new("git").args(["rev-parse", "HEAD"]).output().ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        let metadata = json!({
+            "session": session,
+            "status": "running",
+            "start_time": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64(),
+            "end_time": Value::Null,
+            "pid": std::process::id(),
+            "map": ServerConfig::get_str("map"),
+            "git_hash": git_hash,
+            "seed": Simulation::seed(),
+            "config": "config.yaml"
+        });
+        Self::storeMetadata(folder, &metadata);
+    }
+
+    /// Reads session metadata from session folder
+    fn loadMetadata(folder: &str) -> Option<Value>
     {
-        Self::init();
+        let content = read_to_string(folder.to_string() + SESSION_METADATA_FILE).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Writes session metadata atomically. Crash during write does not corrupt previous content.
+    fn storeMetadata(folder: &str, metadata: &Value)
+    {
+        let tmp_path = folder.to_string() + SESSION_METADATA_FILE + ".tmp";
+        write(&tmp_path, serde_json::to_string_pretty(metadata).unwrap()).expect("Unable to write session metadata");
+        rename(&tmp_path, folder.to_string() + SESSION_METADATA_FILE).expect("Unable to write session metadata");
+    }
+
+    /// Marks session left by crashed run. Marker file exists only if previous run did not end properly
+    fn markCrashedSession()
+    {
+        if let Ok(previous) = read_to_string(SESSION_MARKER_PATH)
+        {
+            let folder = LOG_FOLDER.to_string() + previous.trim() + "/";
+            let metadata = Self::loadMetadata(&folder);
+            if metadata.as_ref().is_some_and(isSessionAlive)
+            {
+                printWarn!("Previous session {} is still running", previous.trim());
+                return;
+            }
+            if let Some(mut metadata) = metadata
+            {
+                if metadata["status"] == "running"
+                {
+                    metadata["status"] = json!("crashed");
+                    Self::storeMetadata(&folder, &metadata);
+                }
+            }
+            printWarn!("Previous session {} did not end properly", previous.trim());
+        }
+    }
+
+    /// Removes the oldest session folders above limit from configuration. Zero keeps all sessions.
+    /// Sessions of running servers and session specified by keep are never removed.
+    fn removeOldSessions(current: &str, keep_session: Option<&str>)
+    {
+        let keep = ServerConfig::get_usize("log_sessions_keep");
+        if keep == 0
+        {
+            return;
+        }
+        let mut sessions: Vec<String> = match read_dir(LOG_FOLDER) {
+            Ok(dir) => dir.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+                .filter(|name| isSessionName(name) && name != current && Some(name.as_str()) != keep_session)
+                .filter(|name| !Self::loadMetadata(&(LOG_FOLDER.to_string() + name + "/")).as_ref().is_some_and(isSessionAlive))
+                .collect(),
+            Err(_) => return,
+        };
+        sessions.sort_by_key(|name| sessionOrder(name));
+        let to_remove = (sessions.len() + 1).saturating_sub(keep);
+        for name in sessions.iter().take(to_remove)
+        {
+            match remove_dir_all(LOG_FOLDER.to_string() + name) {
+                Ok(_) => printDebug!("Removed old session {}", name),
+                Err(e) => printWarn!("Unable to remove old session {}: {}", name, e),
+            }
+        }
+    }
+
+    /// Starts log session. Session specified by keep (name or folder path) is protected from retention
+    pub fn startSession(keep: Option<&str>)
+    {
+        let keep = keep.and_then(|session| Path::new(session).file_name()).and_then(|name| name.to_str());
+        Self::init(keep);
     }
 
     /// End log session
@@ -102,23 +401,158 @@ impl Logger
         let session = SESSION.lock().unwrap();
         if !session.is_empty()
         {
-            remove_file("./logs/session").expect("Unable to remove session file.");
+            let folder = LOG_FOLDER.to_string() + session.as_str() + "/";
+            if let Some(mut metadata) = Self::loadMetadata(&folder)
+            {
+                metadata["status"] = json!("finished");
+                metadata["end_time"] = json!(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64());
+                Self::storeMetadata(&folder, &metadata);
+            }
+            if read_to_string(SESSION_MARKER_PATH).is_ok_and(|marker| marker.trim() == session.as_str())
+            {
+                remove_file(SESSION_MARKER_PATH).expect("Unable to remove session file.");
+            }
         }
     }
 
-    /// Print wrapper that add extra prefix and color of message. Message is also logged to file
+    /// Logs server message. Message is build only if level is not filtered out. Used by printLog! family of macros
+    pub fn server<F: FnOnce() -> String>(level: LogLevel, msg: F)
+    {
+        Self::write(level, "Server", None, COLOR_YELLOW, msg);
+    }
+
+    /// Print wrapper that add extra prefix and color of message. Message is also logged to file. Message has INFO level
     pub fn print(name: &str, source: &str, color: &str, msg: &str)
     {
-        let mut log_file = crate::logger::LOG_FILE.lock().unwrap();
-        let time = crate::logger::START_TIME.lock().unwrap(); 
-        let time_elapsed = time.unwrap().elapsed().as_secs_f32();
+        Self::printLevel(LogLevel::INFO, name, source, color, msg);
+    }
+
+    /// Print wrapper that add extra prefix and color of message. Message is also logged to file
+    pub fn printLevel(level: LogLevel, name: &str, source: &str, color: &str, msg: &str)
+    {
+        Self::write(level, name, Some(source), color, || msg.to_string());
+    }
+
+    /// Filters, formats and writes message to terminal and log file.
+    /// Settings are locked only for filtering, message is built and printed without any lock held
+    fn write<F: FnOnce() -> String>(level: LogLevel, name: &str, source: Option<&str>, color: &str, msg: F)
+    {
+        let mut settings_lck = SETTINGS.lock().unwrap();
+        let settings = settings_lck.get_or_insert_with(LogSettings::fromConfig);
+        if !settings.enabled(level, name, source)
+        {
+            return;
+        }
+        let (colors, json, rotate_size, rotate_period) = (settings.colors, settings.json, settings.rotate_size, settings.rotate_period);
+        drop(settings_lck);
+        let msg = msg();
+        let time = START_TIME.lock().unwrap();
+        let time_elapsed = time.map_or(0.0, |t| t.elapsed().as_secs_f32());
         drop(time);
-        if let Some(file) = log_file.as_mut()
+
+        let mut prefix = format!("[{}]", name);
+        if let Some(source) = source
         {
-            file.write(format!("{:9.3}[{}][{}] ", time_elapsed ,name, source).as_bytes()).unwrap();
-            file.write(msg.as_bytes()).expect("Unable to write log");
-            file.write(b"\n").unwrap();
-            println!("{}{:9.3} {}[{}][{}] {}",COLOR_WHITE,time_elapsed,color, name, source, msg);
+            prefix.push_str(&format!("[{}]", source));
         }
+        let color = if level <= LogLevel::WARN { COLOR_RED } else { color };
+        if colors
+        {
+            println!("{}{:9.3} {:5} {}{} {}{}", COLOR_WHITE, time_elapsed, level.to_str().to_uppercase(),
+                color, prefix, msg, COLOR_NORMAL);
+        }
+        else
+        {
+            println!("{:9.3} {:5} {} {}", time_elapsed, level.to_str().to_uppercase(), prefix, msg);
+        }
+
+        let line = if json
+        {
+            json!({
+                "time": time_elapsed,
+                "level": level.to_str(),
+                "name": name,
+                "source": source,
+                "msg": msg
+            }).to_string()
+        }
+        else
+        {
+            format!("{:9.3} {:5} {} {}", time_elapsed, level.to_str().to_uppercase(), prefix, msg)
+        };
+
+        let mut log_file = LOG_FILE.lock().unwrap();
+        if let Some(log_file) = log_file.as_mut()
+        {
+            log_file.writeLine(&line);
+            log_file.rotateIfNeeded(rotate_size, rotate_period);
+        }
+    }
+}
+
+/// Checks if session metadata belongs to server process that is still running
+fn isSessionAlive(metadata: &Value) -> bool
+{
+    metadata["status"] == "running" && metadata["pid"].as_u64()
+        .is_some_and(|pid| pid != std::process::id() as u64 && Path::new(&format!("/proc/{}", pid)).exists())
+}
+
+/// Checks if folder name is session name: UNIX time with optional numeric suffix
+fn isSessionName(name: &str) -> bool
+{
+    let mut parts = name.splitn(2, '_');
+    let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    parts.next().is_some_and(is_number) && parts.next().is_none_or(is_number)
+}
+
+/// Returns sort key of session name. Sessions are ordered by start time and suffix
+fn sessionOrder(name: &str) -> (u64, u64)
+{
+    let mut parts = name.splitn(2, '_').map(|part| part.parse::<u64>().unwrap_or(0));
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(overrides: &[(&str, LogLevel)]) -> LogSettings
+    {
+        LogSettings {
+            level: LogLevel::INFO,
+            overrides: overrides.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            json: false,
+            colors: false,
+            rotate_size: 0,
+            rotate_period: Duration::ZERO
+        }
+    }
+
+    #[test]
+    fn default_level_filters_messages() {
+        let s = settings(&[]);
+        assert!(s.enabled(LogLevel::ERROR, "Server", None));
+        assert!(s.enabled(LogLevel::INFO, "Server", None));
+        assert!(!s.enabled(LogLevel::DEBUG, "Server", None));
+    }
+
+    #[test]
+    fn most_specific_override_wins() {
+        let s = settings(&[("sim", LogLevel::WARN), ("alpha/sim", LogLevel::TRACE), ("drop", LogLevel::ERROR)]);
+        assert!(s.enabled(LogLevel::TRACE, "alpha", Some("sim")));
+        assert!(!s.enabled(LogLevel::INFO, "beta", Some("sim")));
+        assert!(s.enabled(LogLevel::INFO, "beta", Some("ctrl")));
+        assert!(!s.enabled(LogLevel::WARN, "drop", Some("physic")));
+    }
+
+    #[test]
+    fn session_names_are_recognized_and_ordered() {
+        assert!(isSessionName("1697715036"));
+        assert!(isSessionName("1697715036_2"));
+        assert!(!isSessionName("check.m"));
+        assert!(!isSessionName("1697715036_"));
+        assert!(!isSessionName("session"));
+        assert!(sessionOrder("1697715036_2") > sessionOrder("1697715036_1"));
+        assert!(sessionOrder("1697715037") > sessionOrder("1697715036_10"));
     }
-}
\ No newline at end of file
+}
@@ -13,16 +13,37 @@ pub mod objects;
 pub mod config;
 pub mod obj;
 pub mod map;
+pub mod bvh;
 pub mod cargo;
 pub mod notification;
 pub mod checksum;
 pub mod logger;
+pub mod recorder;
+pub mod replay;
+pub mod simulation;
+pub mod turbulence;
+pub mod wind;
+pub mod weather;
+pub mod airflow;
+pub mod lockstep;
+pub mod forces;
 
 fn main() {
+    // Replay recorded session instead of simulation: --replay SESSION
+    let args: Vec<String> = std::env::args().collect();
+    let replay_session = args.iter().position(|arg| arg == "--replay")
+        .map(|i| args.get(i + 1).expect("Missing session after --replay").clone());
+
     // Start logger and check if asset were changed
-    logger::Logger::startSession();
+    logger::Logger::startSession(replay_session.as_deref());
     checksum::calcChecksum();
+    if replay_session.is_none() && config::ServerConfig::get_bool("recorder_enabled")
+    {
+        recorder::Recorder::init(&config::ServerConfig::get_str("recorder_format"));
+    }
 
+    simulation::Simulation::start();
+    simulation::Simulation::setLockstep(replay_session.is_none() && config::ServerConfig::get_bool("lockstep"));
     let ctx: zmq::Context = zmq::Context::new();
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -44,6 +65,33 @@ fn main() {
         });
     }
     
+    if let Some(session) = replay_session
+    {
+        let folder = if session.contains('/') { session } else { format!("./logs/{}/", session) };
+        notification::Notification::init(ctx.clone(),
+            &(config::ServerConfig::get_usize("notification_port")));
+        let _replay = replay::Replay::new(ctx.clone(), &folder);
+        while running.load(Ordering::SeqCst) {
+            thread::sleep(time::Duration::from_millis(300));
+        }
+        printLog!("Bye!");
+        drop(_replay);
+        drop(ctx);
+        logger::Logger::endSession();
+        return;
+    }
+
+    // Load map before any child process is started, so failure leaves nothing behind
+    let map = match collision::CollisionDetector::loadMap() {
+        Ok(map) => map,
+        Err(e) => {
+            printError!("Unable to load map: {}", e);
+            recorder::Recorder::close();
+            logger::Logger::endSession();
+            std::process::exit(1);
+        }
+    };
+
     // Initialize simulation processes
     let stopSocket = ctx.socket(zmq::SocketType::PUB).unwrap();
     stopSocket.bind("inproc://stop").unwrap();
@@ -53,10 +101,13 @@ fn main() {
         config::ServerConfig::get_usize("object_port"))));
     let _drones = Arc::new(Mutex::new(drones::Drones::new(ctx.clone(),_objects.clone())));
     let _cargo = Arc::new(Mutex::new(cargo::Cargo::new(_drones.clone(), _objects.clone())));
-    let _clients = clients::Clients::new(ctx.clone(),_drones.clone(), _cargo.clone());
 
     let _atmosphere = atmosphere::Atmosphere::new(_drones.clone(),_objects.clone());
-    let _colision_detector = collision::CollisionDetector::new(_drones.clone(),_objects.clone());
+    let _clients = clients::Clients::new(ctx.clone(),_drones.clone(), _cargo.clone(), _atmosphere.getWindField());
+    let _colision_detector = collision::CollisionDetector::new(_drones.clone(),_objects.clone(), map);
+    let _lockstep = if simulation::Simulation::isLockstep() {
+        Some(lockstep::Lockstep::new(_drones.clone(), _objects.clone()))
+    } else { None };
 
     // Wait until simulation is over
     while running.load(Ordering::SeqCst) {
@@ -65,6 +116,7 @@ fn main() {
 
     // Free resources
     printLog!("Bye!");
+    drop(_lockstep);
     stopSocket.send("TERMINATE", 0).unwrap();
     let mut drones_lck = _drones.lock().unwrap();
     drones_lck.removeAllUAV();
@@ -77,5 +129,6 @@ fn main() {
     drop(_drones);
     drop(_objects);
     drop(ctx);
+    recorder::Recorder::close();
     logger::Logger::endSession();
 }
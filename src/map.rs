@@ -1,7 +1,64 @@
 use nalgebra::Vector3;
 use std::collections::{HashMap,HashSet};
+use std::fs::File;
+use serde_yaml::Value;
 use crate::obj::{Obj,Face};
-use crate::printLog;
+use crate::bvh::{Bvh, Aabb};
+use crate::{printLog, printWarn};
+
+/// Spatial index of map faces used by collision queries
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapIndex
+{
+    /// uniform grid of chunks, face is stored in every chunk it spans
+    GRID,
+    /// bounding volume hierarchy over faces
+    BVH,
+}
+
+impl MapIndex
+{
+    /// Parses index name from config: grid or bvh
+    pub fn parse(name: &str) -> Option<Self>
+    {
+        match name {
+            "grid" => Some(MapIndex::GRID),
+            "bvh" => Some(MapIndex::BVH),
+            _ => None,
+        }
+    }
+}
+
+/// Physical properties of map surface made of single material
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Surface
+{
+    /// coefficient of restitution
+    pub COR: f32,
+    /// static friction factor
+    pub mi_s: f32,
+    /// dynamic friction factor
+    pub mi_d: f32,
+    /// touching surface destroys UAV or object (e.g. water)
+    pub deadly: bool,
+    /// UAV may land on surface
+    pub landing_pad: bool,
+}
+
+/// Collision tolerances and default surface coefficients of map
+#[derive(Copy, Clone, Debug)]
+pub struct MapParams
+{
+    pub collisionPlusEps: f32,
+    pub collisionMinusEps: f32,
+    /// coefficient of restitution of faces without surface properties
+    pub COR: f32,
+    /// static friction factor of faces without surface properties
+    pub mi_s: f32,
+    /// dynamic friction factor of faces without surface properties
+    pub mi_d: f32,
+    pub minimalDist: f32,
+}
 
 /// Simulation map
 pub struct Map
@@ -10,7 +67,13 @@ pub struct Map
     _min: Vector3<f32>,
     _max: Vector3<f32>,
     _step: Vector3<f32>,
+    /// number of chunks along axes
+    _grid: Vector3<usize>,
     facesInChunk: HashMap<Vector3<usize>,HashSet<Face>>,
+    index: MapIndex,
+    bvh: Bvh,
+    /// surface properties of map materials by material index. Faces without entry use default coefficients
+    surfaces: HashMap<usize,Surface>,
 
     pub collisionPlusEps: f32,
     pub collisionMinusEps: f32,
@@ -23,27 +86,79 @@ pub struct Map
 impl Map
 {
     /// Constructor
-    pub fn new(path: &str, collisionPlusEps: f32, collisionMinusEps: f32, grid: Vector3<f32>,
-        COR: f32, mi_s: f32, mi_d: f32, minimalDist: f32) -> Self
+    pub fn new(path: &str, index: MapIndex, grid: Vector3<f32>, params: MapParams) -> Result<Self, String>
     {
-        let walls = Obj::from_file(path);
+        let MapParams { collisionPlusEps, collisionMinusEps, COR, mi_s, mi_d, minimalDist } = params;
+        let walls = Obj::from_file(path)?;
         let (min,max) = walls.boundingBox();
-        let step = (max-min).component_div(&grid);
+        let grid = grid.map(|g| g.max(1.0).floor());
+        // Flat map would have zero chunk size along one axis
+        let step = (max-min).component_div(&grid).sup(&Vector3::repeat(f32::EPSILON));
 
         printLog!("Min: {} Max: {}", min,max);
-        printLog!("Chunk size: {}", step);
 
         let facesInChunk =  HashMap::<Vector3<usize>,HashSet<Face>>::new();
+        // Hierarchy is built for every index, it serves nearest wall queries
+        let bvh = Bvh::build(&walls.faces);
 
-        let mut map = Map{_walls: walls, _min: min, _max: max, _step: step,
-            facesInChunk, collisionPlusEps, collisionMinusEps,
+        let mut map = Map{_walls: walls, _min: min, _max: max, _step: step, _grid: grid.map(|g| g as usize),
+            facesInChunk, index, bvh, surfaces: HashMap::new(), collisionPlusEps, collisionMinusEps,
             COR,
             mi_s,
             mi_d,
             minimalDist
         };
-        map.insertFace();
-        map
+        if index == MapIndex::GRID
+        {
+            printLog!("Chunk size: {}", step);
+            map.insertFace();
+        }
+        printLog!("Map collision index: {:?}", index);
+        Ok(map)
+    }
+
+    /// Loads surface properties of map materials from YAML table. Missing properties are taken from default coefficients.
+    /// Returns number of materials found in map
+    pub fn loadSurfaces(&mut self, path: &str) -> Result<usize, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let table: Value = serde_yaml::from_reader(file).map_err(|e| format!("{}: {}", path, e))?;
+        let materials = table["materials"].as_mapping().ok_or(format!("{}: missing materials", path))?;
+        let default = self.defaultSurface();
+        for (name, params) in materials.iter()
+        {
+            let name = name.as_str().ok_or(format!("{}: invalid material name", path))?;
+            let number = |key: &str, default: f32| -> Result<f32, String> {
+                match &params[key] {
+                    Value::Null => Ok(default),
+                    value => value.as_f64().map(|v| v as f32).ok_or(format!("{}: material {}: invalid {}", path, name, key)),
+                }
+            };
+            let surface = Surface {
+                COR: number("COR", default.COR)?,
+                mi_s: number("mi_s", default.mi_s)?,
+                mi_d: number("mi_d", default.mi_d)?,
+                deadly: params["deadly"].as_bool().unwrap_or(false),
+                landing_pad: params["landing_pad"].as_bool().unwrap_or(false),
+            };
+            match self._walls.materials.iter().position(|m| m == name) {
+                Some(material) => { self.surfaces.insert(material, surface); },
+                None => printWarn!("Material {} from {} is not used in map", name, path),
+            }
+        }
+        Ok(self.surfaces.len())
+    }
+
+    /// Surface used by faces without material properties
+    fn defaultSurface(&self) -> Surface
+    {
+        Surface { COR: self.COR, mi_s: self.mi_s, mi_d: self.mi_d, deadly: false, landing_pad: false }
+    }
+
+    /// Returns surface properties of face material
+    pub fn surfaceOf(&self, face: &Face) -> Surface
+    {
+        face.material.and_then(|m| self.surfaces.get(&m)).copied().unwrap_or(self.defaultSurface())
     }
 
     /// Checks if in specified point there is collision with map walls. 
@@ -52,22 +167,18 @@ impl Map
     pub fn checkWalls(&self, point: Vector3<f32>, radius: f32) -> Vec<Vector3<f32>>
     {
         let mut normalsInColisionPoint = Vec::new();
-        let chunk = self.calcChunk(point);
-        if let Some(faces) = self.facesInChunk.get(&chunk)
-        {
-            for face in faces {
-                if let (true, dist) = face.projectPoint(point)
+        for face in self.facesNear(point, radius) {
+            if let (true, dist) = face.projectPoint(point)
+            {
+                if dist - radius <= self.collisionPlusEps && dist - radius >= self.collisionMinusEps
                 {
-                    if dist - radius <= self.collisionPlusEps && dist - radius >= self.collisionMinusEps
-                    {
-                        normalsInColisionPoint.push(face.normal)
-                    }
-                    if !face.has_true_normals
+                    normalsInColisionPoint.push(face.normal)
+                }
+                if !face.has_true_normals
+                {
+                    if -dist - radius <= self.collisionPlusEps && -dist - radius >= self.collisionMinusEps
                     {
-                        if -dist - radius <= self.collisionPlusEps && -dist - radius >= self.collisionMinusEps
-                        {
-                            normalsInColisionPoint.push(-face.normal)
-                        }
+                        normalsInColisionPoint.push(-face.normal)
                     }
                 }
             }
@@ -75,36 +186,44 @@ impl Map
         normalsInColisionPoint
     }
 
-    /// Modified checkWalls, predict position in looptime
-    pub fn checkWalls2(&self, start_point: Vector3<f32>,velocity: Vector3<f32>, dt: f32, radius: f32) -> Vec<Vector3<f32>>
+    /// Sweeps sphere from start point with velocity during dt. Sphere is extended by collisionPlusEps to catch resting contacts.
+    /// Returns time of impact in s, contact point, normal vector pointing from wall towards sphere and surface of wall.
+    /// If there is no collisions, return None. Faces with true normals are hit only from the front.
+    pub fn sweepSphere(&self, start: Vector3<f32>, velocity: Vector3<f32>, dt: f32, radius: f32) -> Option<(f32,Vector3<f32>,Vector3<f32>,Surface)>
     {
-        let mut normalsInColisionPoint = Vec::new();
-
-        for s in [0.0f32, 0.33f32, 0.66f32, 1.0f32]
+        let motion = velocity * dt;
+        let radius = radius + self.collisionPlusEps;
+        let bounds = Aabb::around(&start, radius).merge(&Aabb::around(&(start + motion), radius));
+        let mut best: Option<(f32,Vector3<f32>,Vector3<f32>,&Face)> = None;
+        for face in self.facesInBox(&bounds)
         {
-            let point = start_point + s * dt * velocity;
-            let chunk = self.calcChunk(point);
-            if let Some(faces) = self.facesInChunk.get(&chunk)
+            let Some((time, point)) = face.sweepSphere(&start, &motion, radius) else {
+                continue;
+            };
+            if best.is_some_and(|b| b.0 <= time)
             {
-                for face in faces {
-                    if let (true, dist) = face.projectPoint(point)
-                    {
-                        if dist - radius <= self.collisionPlusEps && dist - radius >= self.collisionMinusEps
-                        {
-                            normalsInColisionPoint.push(face.normal)
-                        }
-                        if !face.has_true_normals
-                        {
-                            if -dist - radius <= self.collisionPlusEps && -dist - radius >= self.collisionMinusEps
-                            {
-                                normalsInColisionPoint.push(-face.normal)
-                            }
-                        }
-                    }
-                }
+                continue;
+            }
+            let offset = start + motion * time - point;
+            let normal = if offset.norm_squared() > f32::EPSILON
+            {
+                offset.normalize()
+            }
+            else if motion.dot(&face.normal) < 0.0
+            {
+                face.normal
             }
+            else
+            {
+                -face.normal
+            };
+            if face.has_true_normals && normal.dot(&face.normal) < 0.0
+            {
+                continue;
+            }
+            best = Some((time, point, normal, face));
         }
-        normalsInColisionPoint
+        best.map(|(time, point, normal, face)| (time * dt, point, normal, self.surfaceOf(face)))
     }
 
     /// Checks if in specified point there is collision with map walls. 
@@ -115,19 +234,15 @@ impl Map
     {
         let mut bestNormal = Vector3::<f32>::zeros();
         let mut bestDepth = self.collisionPlusEps;
-        let chunk = self.calcChunk(point);
-        if let Some(faces) = self.facesInChunk.get(&chunk)
-        {
-            for face in faces {
-                if let (true, dist) = face.projectPoint(point)
+        for face in self.facesNear(point, 0.0) {
+            if let (true, dist) = face.projectPoint(point)
+            {
+                if dist <= self.collisionPlusEps && dist >= self.collisionMinusEps
                 {
-                    if dist <= self.collisionPlusEps && dist >= self.collisionMinusEps
+                    if dist < bestDepth
                     {
-                        if dist < bestDepth
-                        {
-                            bestDepth = dist;
-                            bestNormal = face.normal;
-                        }
+                        bestDepth = dist;
+                        bestNormal = face.normal;
                     }
                 }
             }
@@ -140,54 +255,195 @@ impl Map
     }
 
     /// Checks if in specified point there will collide with map walls in specificed dt. 
-    /// Returns normal vector of wall that will be cross in next dt
+    /// Returns distance, normal vector and surface of wall that will be cross in next dt
     /// If there is no collisions, return None
-    pub fn checkWallsBest2(&self, point: Vector3<f32>, velocity: Vector3<f32>, dt: f32) -> Option<(f32,Vector3<f32>)>
+    pub fn checkWallsBest2(&self, point: Vector3<f32>, velocity: Vector3<f32>, dt: f32) -> Option<(f32,Vector3<f32>,Surface)>
     {
+        if velocity.norm_squared() == 0.0
+        {
+            return None;
+        }
         let inRange = (velocity.norm() * dt).max(-self.collisionMinusEps);
         let dir = velocity.normalize();
         let mut bestNormal = Vector3::<f32>::zeros();
         let mut bestDist = inRange;
+        let mut bestFace = None;
 
-        let chunk = self.calcChunk(point);
-        if let Some(faces) = self.facesInChunk.get(&chunk)
+        if self.index == MapIndex::BVH
         {
-            for face in faces {
-                if let (true, dist) = face.rayIntersection(point,dir)
-                {
-                    if dist < bestDist
+            let faces = &self._walls.faces;
+            let hit = self.bvh.raycast(&point, &dir, inRange, |f| {
+                let face = &faces[f];
+                match face.rayIntersection(point, dir) {
+                    (true, dist) if velocity.dot(&face.normal) < 0.0f32 || !face.has_true_normals => Some(dist),
+                    _ => None,
+                }
+            });
+            return hit.map(|(dist, f)| {
+                let face = &faces[f];
+                let normal = if velocity.dot(&face.normal) < 0.0f32 { face.normal } else { -face.normal };
+                (dist, normal, self.surfaceOf(face))
+            });
+        }
+
+        for (chunk, exit) in self.chunksOnRay(point, dir, inRange)
+        {
+            if let Some(faces) = self.facesInChunk.get(&chunk)
+            {
+                for face in faces {
+                    if let (true, dist) = face.rayIntersection(point,dir)
                     {
-                        if velocity.dot(&face.normal) < 0.0f32
+                        if dist < bestDist
                         {
-                            bestDist = dist;
-                            bestNormal = face.normal;
+                            if velocity.dot(&face.normal) < 0.0f32
+                            {
+                                bestDist = dist;
+                                bestNormal = face.normal;
+                                bestFace = Some(face);
+                            }
+                            else if !face.has_true_normals
+                            {
+                                bestDist = dist;
+                                bestNormal = -face.normal;
+                                bestFace = Some(face);
+                            }
                         }
-                        else if !face.has_true_normals
+                    }
+                }
+            }
+            // Hits in further chunks can not be closer
+            if bestDist <= exit
+            {
+                break;
+            }
+        }
+        bestFace.map(|face| (bestDist, bestNormal, self.surfaceOf(face)))
+    }
+
+    /// Finds wall closest to point not farther than max_dist.
+    /// Returns distance, closest point on wall and normal vector of wall pointing towards point.
+    pub fn nearestWall(&self, point: Vector3<f32>, max_dist: f32) -> Option<(f32,Vector3<f32>,Vector3<f32>)>
+    {
+        let (dist, closest, f) = self.bvh.nearest(&self._walls.faces, &point, max_dist)?;
+        let normal = self._walls.faces[f].normal;
+        let normal = if (point - closest).dot(&normal) < 0.0 { -normal } else { normal };
+        Some((dist, closest, normal))
+    }
+
+    /// Returns faces that may be closer to point than radius plus collision tolerance
+    fn facesNear(&self, point: Vector3<f32>, radius: f32) -> Vec<&Face>
+    {
+        match self.index {
+            MapIndex::GRID => self.calcChunk(point)
+                .and_then(|chunk| self.facesInChunk.get(&chunk))
+                .map(|faces| faces.iter().collect())
+                .unwrap_or_default(),
+            MapIndex::BVH => {
+                let margin = radius + self.collisionPlusEps.max(-self.collisionMinusEps);
+                self.facesInBox(&Aabb::around(&point, margin))
+            }
+        }
+    }
+
+    /// Returns faces which may cross specified box. Every face is returned once
+    fn facesInBox(&self, bounds: &Aabb) -> Vec<&Face>
+    {
+        let mut faces = Vec::new();
+        match self.index {
+            MapIndex::GRID => {
+                if !self.gridBounds().overlaps(bounds)
+                {
+                    return faces;
+                }
+                let (from, to) = (self.clampChunk(bounds.min), self.clampChunk(bounds.max));
+                let mut seen = HashSet::new();
+                for i in from.x..=to.x
+                {
+                    for j in from.y..=to.y
+                    {
+                        for k in from.z..=to.z
                         {
-                            bestDist = dist;
-                            bestNormal = -face.normal;
+                            if let Some(chunk_faces) = self.facesInChunk.get(&Vector3::new(i,j,k))
+                            {
+                                faces.extend(chunk_faces.iter().filter(|face| seen.insert(face.id)));
+                            }
                         }
                     }
                 }
             }
+            MapIndex::BVH => self.bvh.query(bounds, |f| faces.push(&self._walls.faces[f])),
         }
-        if bestDist < inRange
-        {
-            return Some((bestDist,bestNormal));
-        }
-        None
+        faces
+    }
+
+    /// Returns box covered by chunks
+    fn gridBounds(&self) -> Aabb
+    {
+        Aabb { min: self._min, max: self._min + self._step.component_mul(&self._grid.cast::<f32>()) }
+    }
+
+    /// Calculates chunk for specified point. Returns None if point is outside the grid
+    fn calcChunk(&self, point: Vector3<f32>) -> Option<Vector3<usize>>
+    {
+        let pos = (point - self._min).component_div(&self._step);
+        // Small tolerance keeps map vertices on the boundary inside despite rounding
+        let inside = (0..3).all(|axis| pos[axis] >= -1e-4 && pos[axis] <= self._grid[axis] as f32 + 1e-4);
+        inside.then(|| self.clampChunk(point))
     }
 
-    /// Calculates chunk that for specified point
-    fn calcChunk(&self, point: Vector3<f32>) -> Vector3<usize>
+    /// Calculates chunk for specified point. Points outside the grid are moved to the nearest chunk
+    fn clampChunk(&self, point: Vector3<f32>) -> Vector3<usize>
     {
-        let pos =  (point -  self._min).component_div(&self._step);
-        let chunk = Vector3::new(
-            pos.x.floor() as usize,
-            pos.y.floor() as usize,
-            pos.z.floor() as usize,
-        );
-        chunk
+        let pos = (point - self._min).component_div(&self._step);
+        Vector3::from_fn(|axis, _| (pos[axis].max(0.0).floor() as usize).min(self._grid[axis] - 1))
+    }
+
+    /// Returns chunks crossed by ray from point in direction dir up to length, ordered along ray (3D DDA).
+    /// Every chunk comes with distance along ray where ray leaves it.
+    fn chunksOnRay(&self, point: Vector3<f32>, dir: Vector3<f32>, length: f32) -> Vec<(Vector3<usize>, f32)>
+    {
+        let mut chunks = Vec::new();
+        let Some(entry) = self.gridBounds().rayEntry(&point, &dir, length) else {
+            return chunks;
+        };
+        let start = (point + dir * entry - self._min).component_div(&self._step);
+        let mut chunk = Vector3::<isize>::zeros();
+        let mut next = Vector3::repeat(f32::INFINITY);
+        let mut delta = Vector3::repeat(f32::INFINITY);
+        let mut unit = Vector3::<isize>::zeros();
+        for axis in 0..3
+        {
+            let last = self._grid[axis] as isize - 1;
+            chunk[axis] = (start[axis].floor() as isize).clamp(0, last);
+            if dir[axis] > 0.0
+            {
+                unit[axis] = 1;
+                delta[axis] = self._step[axis] / dir[axis];
+                next[axis] = entry + (chunk[axis] as f32 + 1.0 - start[axis]) * delta[axis];
+            }
+            else if dir[axis] < 0.0
+            {
+                unit[axis] = -1;
+                delta[axis] = -self._step[axis] / dir[axis];
+                next[axis] = entry + (start[axis] - chunk[axis] as f32) * delta[axis];
+            }
+        }
+        loop
+        {
+            let axis = next.imin();
+            chunks.push((chunk.map(|c| c as usize), next[axis]));
+            if next[axis] > length
+            {
+                break;
+            }
+            chunk[axis] += unit[axis];
+            if chunk[axis] < 0 || chunk[axis] >= self._grid[axis] as isize
+            {
+                break;
+            }
+            next[axis] += delta[axis];
+        }
+        chunks
     }
 
     /// Splits faces into chunks
@@ -195,7 +451,7 @@ impl Map
     {
         for face in &self._walls.faces
         {
-            let chunks  = face._vertices.map(|p| self.calcChunk(p));
+            let chunks  = face._vertices.map(|p| self.clampChunk(p));
             let mut minChunks = chunks[0];
             let mut maxChunks = chunks[0];
             for chunk in chunks
@@ -233,3 +489,105 @@ impl Map
         (self._min,self._max)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::Instant;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    fn testParams() -> MapParams
+    {
+        MapParams { collisionPlusEps: 0.15, collisionMinusEps: -0.4, COR: 0.5, mi_s: 0.4, mi_d: 0.3, minimalDist: 1.0 }
+    }
+
+    #[test]
+    fn grid_ray_crosses_chunks() {
+        let path = std::env::temp_dir().join("uav_aggregator_grid_ray.obj");
+        std::fs::write(&path, "v 0 0 0\nv 9 0 0\nv 9 10 0\nv 9 10 10\nv 9 0 10\nf 2 3 4\nf 2 4 5\n").unwrap();
+        let map = Map::new(path.to_str().unwrap(), MapIndex::GRID, Vector3::new(5.0, 5.0, 5.0), testParams()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (dist, normal, _) = map.checkWallsBest2(Vector3::new(1.0, 5.0, 5.0), Vector3::new(100.0, 0.0, 0.0), 0.1).unwrap();
+        assert!((dist - 8.0).abs() < 1e-4);
+        assert_eq!(normal.x.abs(), 1.0);
+        // Ray entering grid from outside
+        assert!(map.checkWallsBest2(Vector3::new(-3.0, 5.0, 5.0), Vector3::new(100.0, 0.0, 0.0), 0.2).is_some());
+        assert!(map.checkWallsBest2(Vector3::new(1.0, 5.0, 5.0), Vector3::new(100.0, 0.0, 0.0), 0.05).is_none());
+        assert_eq!(map.calcChunk(Vector3::new(-1.0, 5.0, 5.0)), None);
+        assert_eq!(map.calcChunk(Vector3::new(9.0, 10.0, 10.0)), Some(Vector3::new(4, 4, 4)));
+    }
+
+    #[test]
+    fn map_surfaces_follow_face_materials() {
+        let dir = std::env::temp_dir();
+        let obj_path = dir.join("uav_aggregator_surfaces.obj");
+        let table_path = dir.join("uav_aggregator_surfaces.yaml");
+        std::fs::write(&obj_path, "mtllib walls.mtl\nv 9 0 0\nv 9 10 0\nv 9 10 10\nv 9 0 10\nv 0 0 10\nv 0 10 10\n\
+            usemtl water\nf 1 2 3\nusemtl concrete\nf 1 3 4\nf 4 3 6\nusemtl pad\nf 4 6 5\n").unwrap();
+        std::fs::write(&table_path, "materials:\n  water:\n    deadly: true\n  pad:\n    COR: 0.1\n    landing_pad: true\n  sand:\n    COR: 0.0\n").unwrap();
+        let mut map = Map::new(obj_path.to_str().unwrap(), MapIndex::BVH, Vector3::new(5.0, 5.0, 5.0), testParams()).unwrap();
+        assert_eq!(map._walls.materials, vec!["water", "concrete", "pad"]);
+        assert_eq!(map._walls.material_libs, vec!["walls.mtl"]);
+        assert_eq!(map.loadSurfaces(table_path.to_str().unwrap()), Ok(2));
+        std::fs::remove_file(&obj_path).unwrap();
+        std::fs::remove_file(&table_path).unwrap();
+
+        let (_, _, water) = map.checkWallsBest2(Vector3::new(1.0, 8.0, 1.0), Vector3::new(100.0, 0.0, 0.0), 0.1).unwrap();
+        assert!(water.deadly && water.COR == 0.5);
+        let (_, _, concrete) = map.checkWallsBest2(Vector3::new(1.0, 1.0, 8.0), Vector3::new(100.0, 0.0, 0.0), 0.1).unwrap();
+        assert_eq!(concrete, Surface { COR: 0.5, mi_s: 0.4, mi_d: 0.3, deadly: false, landing_pad: false });
+        let (_, _, _, pad) = map.sweepSphere(Vector3::new(2.0, 5.0, 9.0), Vector3::new(0.0, 0.0, 10.0), 0.1, 0.2).unwrap();
+        assert!(pad.landing_pad && pad.COR == 0.1 && pad.mi_s == 0.4);
+    }
+
+    /// Compares grid and BVH on maps from assets with 100k random rays and spheres.
+    /// City ships only model.gltf without its model.bin buffer, so it is skipped until OBJ model is added.
+    /// Results of release build on de_dust2 (grid / BVH):
+    /// - build: 52 ms / 29 ms
+    /// - raycast: 112 ms / 50 ms, 7565 hits each
+    /// - sphere sweep: 589 ms / 79 ms, 11311 contacts each
+    ///
+    /// Run with: cargo test --release map_index_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn map_index_benchmark() {
+        for name in ["de_dust2", "city"]
+        {
+            let path = format!("assets/maps/{}/model/model.obj", name);
+            if !Path::new(&path).is_file()
+            {
+                println!("{}: no OBJ model, skipped", name);
+                continue;
+            }
+            let mut maps = Vec::new();
+            for index in [MapIndex::GRID, MapIndex::BVH]
+            {
+                let start = Instant::now();
+                let map = Map::new(&path, index, Vector3::new(100.0, 100.0, 10.0), testParams()).unwrap();
+                println!("{} {:?}: build {:?}", name, index, start.elapsed());
+                maps.push(map);
+            }
+            let (min, max) = maps[0].getMinMax();
+            let mut rng = StdRng::seed_from_u64(7);
+            let rays: Vec<(Vector3<f32>, Vector3<f32>)> = (0..100_000).map(|_| (
+                Vector3::from_fn(|i, _| rng.gen_range(min[i]..=max[i])),
+                Vector3::from_fn(|_, _| rng.gen_range(-50.0..50.0)),
+            )).collect();
+            let mut hits = Vec::new();
+            for map in maps.iter()
+            {
+                let start = Instant::now();
+                let ray_hits = rays.iter().filter(|(p, v)| map.checkWallsBest2(*p, *v, 0.1).is_some()).count();
+                let ray_time = start.elapsed();
+                let start = Instant::now();
+                let contacts = rays.iter().filter(|(p, v)| map.sweepSphere(*p, *v, 0.1, 0.2).is_some()).count();
+                println!("{} {:?}: raycast {:?} ({} hits), sphere {:?} ({} contacts)",
+                    name, map.index, ray_time, ray_hits, start.elapsed(), contacts);
+                hits.push(ray_hits);
+            }
+            // Both indices follow whole ray
+            assert_eq!(hits[0], hits[1]);
+        }
+    }
+}
@@ -1,13 +1,33 @@
 use std::sync::atomic::{AtomicBool, self};
 use std::sync::Mutex;
+use std::collections::BTreeMap;
+use std::time::Instant;
 use zmq::Socket;
-use crate::printLog;
+use crate::{printLog, recorder::Recorder};
 
 
 /// static variable to check if Notification was initialized
 static READY: AtomicBool = AtomicBool::new(false);
 /// static notify socket used by Notification methods
 static NOTIFY_SOCKET: Mutex<Option<Socket>> = Mutex::new(None);
+/// static store of the last notification per topic and prompt category. Used to serve late joiners.
+static SNAPSHOT: Mutex<BTreeMap<String, Retained>> = Mutex::new(BTreeMap::new());
+
+/// Retained notification. Periodic topics keep only last message,
+/// prompts keep their content to recalculate remaining show time.
+enum Retained
+{
+    Topic(String),
+    Prompt
+    {
+        target_id: isize,
+        category: usize,
+        color: String,
+        show_time_ms: usize,
+        message: String,
+        sent: Instant,
+    },
+}
 
 /// Notify subscribers about simulation less importants events and statuses.
 /// Contains static method to send message and not require class instance to use.
@@ -23,6 +43,8 @@ pub enum PromptCategory
     OVERLOAD = 1,
     COLLISION = 2,
     TERRAIN = 3,
+    WEATHER = 4,
+    DAMAGE = 5,
 }
 
 impl PromptCategory {
@@ -74,16 +96,72 @@ impl Notification
     }
 
     /// Send notification. Instance is not requiered.
+    /// Last message of every topic (text before ':') is retained for late joiners.
     pub fn sendMsg(msg: &str)
+    {
+        if let Some((topic, _)) = msg.split_once(':')
+        {
+            let mut snapshot_lck = SNAPSHOT.lock().unwrap();
+            snapshot_lck.insert(topic.to_string(), Retained::Topic(msg.to_string()));
+        }
+        Self::publish(msg);
+    }
+
+    /// Publish message on notify socket
+    fn publish(msg: &str)
     {
         if !READY.load(atomic::Ordering::Relaxed)
         {
             return;
         }
+        Recorder::recordNotification(msg);
         let socket_lck = NOTIFY_SOCKET.lock().unwrap();
         socket_lck.as_ref().unwrap().send(msg, 0).unwrap(); 
     }
 
+    /// Returns last message of every topic and prompts that are still displayed.
+    /// Prompt show time is reduced by time elapsed since it was sent.
+    /// Used by clients that connect after notifications were sent.
+    pub fn getSnapshot() -> Vec<String>
+    {
+        // Single time reference, so prompt kept by retain has remaining time left
+        let now = Instant::now();
+        let mut snapshot_lck = SNAPSHOT.lock().unwrap();
+        snapshot_lck.retain(|_, retained| match retained {
+            Retained::Topic(_) => true,
+            Retained::Prompt { show_time_ms, sent, .. } =>
+                *show_time_ms == 0 || now.duration_since(*sent).as_millis() < *show_time_ms as u128,
+        });
+        snapshot_lck.values().map(|retained| match retained {
+            Retained::Topic(msg) => msg.clone(),
+            Retained::Prompt { target_id, category, color, show_time_ms, message, sent } =>
+            {
+                // Zero means infinite show time, so finite prompt never drops to it
+                let elapsed = now.duration_since(*sent).as_millis().min(usize::MAX as u128) as usize;
+                let remaining = if *show_time_ms == 0 { 0 } else { show_time_ms.saturating_sub(elapsed).max(1) };
+                Self::formatPrompt(*target_id, *category, color, remaining, message)
+            }
+        }).collect()
+    }
+
+    /// Builds prompt message as described in sendPrompt
+    fn formatPrompt(target_id: isize, category: usize, color: &str, show_time_ms: usize, message: &str) -> String
+    {
+        let mut promptMsg = String::with_capacity(20 + message.len());
+        promptMsg.push_str("p:");
+        promptMsg.push_str(target_id.to_string().as_str());
+        promptMsg.push(',');
+        promptMsg.push_str(category.to_string().as_str());
+        promptMsg.push(',');
+        promptMsg.push_str(color);
+        promptMsg.push(',');
+        promptMsg.push_str(show_time_ms.to_string().as_str());
+        promptMsg.push(',');
+        promptMsg.push_str(message);
+        promptMsg.push(';');
+        promptMsg
+    }
+
     
     /// Send notification about prompt. Notification schema:
     /// p:TARGET,CATEGORY,COLOR,SHOW_TIME,MESSAGE;
@@ -92,23 +170,31 @@ impl Notification
     /// COLOR -- RGB 8bit color as HEX: rrggbb. For example: ORANGE -- FF8000
     /// SHOW_TIME -- how long prompt should be displayed, in miliseconds. Zero means unlimited time, until replaced.
     /// MESSAGE - prompt content. May be empty what mean that prompt in this category should be cleared.
+    /// Last prompt for every TARGET and CATEGORY pair is retained for late joiners.
     pub fn sendPrompt(target_id: isize, category: PromptCategory, color: PromptColor, show_time_ms: usize ,  message: &str)
     {
         if let Some(color) = color.to_hex()
         {
-            let mut promptMsg = String::with_capacity(20 + message.len());
-            promptMsg.push_str("p:");
-            promptMsg.push_str(target_id.to_string().as_str());
-            promptMsg.push(',');
-            promptMsg.push_str(category.to_usize().to_string().as_str());
-            promptMsg.push(',');
-            promptMsg.push_str(color);
-            promptMsg.push(',');
-            promptMsg.push_str(show_time_ms.to_string().as_str());
-            promptMsg.push(',');
-            promptMsg.push_str(message);
-            promptMsg.push(';');
-            Self::sendMsg(&promptMsg);
+            let promptMsg = Self::formatPrompt(target_id, category.to_usize(), color, show_time_ms, message);
+            let key = format!("p:{},{}", target_id, category.to_usize());
+            let mut snapshot_lck = SNAPSHOT.lock().unwrap();
+            if message.is_empty()
+            {
+                snapshot_lck.remove(&key);
+            }
+            else
+            {
+                snapshot_lck.insert(key, Retained::Prompt {
+                    target_id,
+                    category: category.to_usize(),
+                    color: color.to_string(),
+                    show_time_ms,
+                    message: message.to_string(),
+                    sent: Instant::now()
+                });
+            }
+            drop(snapshot_lck);
+            Self::publish(&promptMsg);
         }
     }
 }
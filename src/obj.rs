@@ -1,113 +1,138 @@
-use nalgebra::{Vector3, Matrix3, DMatrix};
+use nalgebra::{Vector2, Vector3, Matrix3, DMatrix};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::hash::{Hash, Hasher};
 
-use crate::printLog;
+use crate::printWarn;
 
 /// Parsed OBJ file
+#[derive(Default)]
 pub struct Obj
 {
     pub _vertices: Vec<Vector3<f32>>,
     pub _normals: Vec<Vector3<f32>>,
-    pub faces: Vec<Face>
+    /// texture coordinates, w component is dropped
+    pub _tex_coords: Vec<Vector2<f32>>,
+    pub faces: Vec<Face>,
+    /// material libraries referenced by mtllib
+    pub material_libs: Vec<String>,
+    /// material names in order of first usemtl, faces refer to them by index
+    pub materials: Vec<String>,
+    /// object and group names in order of first o or g, faces refer to them by index
+    pub groups: Vec<String>,
 }
 
+/// Corner of face statement: indices of vertex, texture coordinate and normal
+type FaceCorner = (usize, Option<usize>, Option<usize>);
+
 impl Obj
 {
     /// Read obj from file
-    pub fn from_file(file_path: &str)-> Self
+    pub fn from_file(file_path: &str) -> Result<Self, String>
     {
         Self::load_from_file(file_path, false)
     }
 
-    /// Read obj from file. Parsing normals and faces may be disable by setting verticesOnly to true.
-    pub fn load_from_file(file_path: &str, verticesOnly: bool) -> Self {
-        let mut vertices = Vec::<Vector3<f32>>::new();
-        let mut normals = Vec::<Vector3<f32>>::new();
-        let mut faces = Vec::<Face>::new();
+    /// Read obj from file. Parsing normals, texture coordinates and faces may be disable by setting verticesOnly to true.
+    pub fn load_from_file(file_path: &str, verticesOnly: bool) -> Result<Self, String>
+    {
+        let file = File::open(file_path).map_err(|e| format!("{}: {}", file_path, e))?;
+        Self::parse(BufReader::new(file), verticesOnly).map_err(|e| format!("{}: {}", file_path, e))
+    }
 
-        let file = File::open(file_path);
-        if file.is_err()
+    /// Parses obj. Polygons are triangulated by ear clipping, self-intersecting ones fall back to fan with warning. Errors contain line number
+    pub fn parse<R: BufRead>(reader: R, verticesOnly: bool) -> Result<Self, String>
+    {
+        let mut obj = Obj::default();
+        let mut material = None;
+        let mut group = None;
+        for (number, line) in reader.lines().enumerate()
         {
-            printLog!("Can not open file: {}. Assumed empty obj.", file_path);
-            return Obj{_vertices: vertices, _normals: normals,faces};
+            line.map_err(|e| e.to_string())
+                .and_then(|line| obj.parseLine(&line, verticesOnly, &mut material, &mut group))
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
         }
-        let reader = BufReader::new(file.unwrap());
+        Ok(obj)
+    }
 
-        for line in reader.lines() 
+    /// Parses single statement. Unsupported statements are ignored
+    fn parseLine(&mut self, line: &str, verticesOnly: bool, material: &mut Option<usize>, group: &mut Option<usize>) -> Result<(), String>
+    {
+        let elements: Vec<&str> = line.split_whitespace().collect();
+        let Some((keyword, args)) = elements.split_first() else {
+            return Ok(());
+        };
+        match *keyword {
+            "v" => self._vertices.push(parseVector(args, "vertex")?),
+            _ if verticesOnly => {}
+            "vn" => self._normals.push(parseVector(args, "normal")?.normalize()),
+            "vt" => {
+                let u = parseNumber(args.first().copied().unwrap_or(""), "texture")?;
+                let v = args.get(1).map_or(Ok(0.0), |v| parseNumber(v, "texture"))?;
+                self._tex_coords.push(Vector2::new(u, v));
+            }
+            "f" => self.parseFace(args, *material, *group)?,
+            "o" | "g" => *group = (!args.is_empty()).then(|| findOrInsert(&mut self.groups, args.join(" "))),
+            "mtllib" => self.material_libs.push(args.join(" ")),
+            "usemtl" => *material = Some(findOrInsert(&mut self.materials, args.join(" "))),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Parses face and splits it into triangles sharing first corner. Degenerated triangles are skipped
+    fn parseFace(&mut self, args: &[&str], material: Option<usize>, group: Option<usize>) -> Result<(), String>
+    {
+        if args.len() < 3
+        {
+            return Err(format!("Face needs at least 3 vertices, got {}", args.len()));
+        }
+        let corners = args.iter().map(|arg| self.parseCorner(arg)).collect::<Result<Vec<FaceCorner>, String>>()?;
+        let points: Vec<Vector3<f32>> = corners.iter().map(|(v, _, _)| self._vertices[*v]).collect();
+        let (triangles, clipped) = triangulate(&points);
+        if !clipped
         {
-            let line = line.expect("Can not read line");
-            let elements: Vec<&str> = line.trim().split_whitespace().collect();
-            
-            if elements.is_empty()
+            printWarn!("Unable to triangulate face {} by ear clipping, fan used", args.join(" "));
+        }
+        for [a, b, c] in triangles
+        {
+            let triangle = [corners[a], corners[b], corners[c]];
+            let vertices = triangle.map(|(v, _, _)| self._vertices[v]);
+            if (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).norm_squared() < 1e-12
             {
+                printWarn!("Degenerated triangle of face {} skipped", args.join(" "));
                 continue;
             }
-
-            match elements[0] {
-                "v" => {
-                    assert_eq!(elements.len(), 4);
-                    let x: f32 = elements[1].parse().expect("Invalid vertex coordinate");
-                    let y: f32 = elements[2].parse().expect("Invalid vertex coordinate");
-                    let z: f32 = elements[3].parse().expect("Invalid vertex coordinate");
-                    vertices.push(Vector3::new(x, y, z));
-                }
-                "vn" => {
-                    if verticesOnly
-                    {
-                        continue;
-                    }
-                    assert_eq!(elements.len(), 4);
-                    let x: f32 = elements[1].parse().expect("Invalid normal coordinate");
-                    let y: f32 = elements[2].parse().expect("Invalid normal coordinate");
-                    let z: f32 = elements[3].parse().expect("Invalid normal coordinate");
-                    normals.push(Vector3::new(x, y, z).normalize());
-                }
-                "f" => {
-                    if verticesOnly
-                    {
-                        continue;
-                    }
-
-                    if elements.len() != 4
-                    {
-                        printLog!("Face {} is not triangle. Skipped", line);
-                        continue;
-                    }
-
-                    let mut face_vertices = [Vector3::zeros();3];
-                    let mut face_normals= [Vector3::zeros();3];
-                    let mut hasNormals = true;
-
-                    for (i, element) in elements[1..].iter().enumerate() {
-                        let items: Vec<String> = element.split('/').map(|s| s.to_string()).collect();
-                        if items.is_empty()
-                        {
-                            printLog!("Face {} is invalid. Skipped", line);
-                            continue;
-                        }
-                        face_vertices[i] = vertices[items[0].parse::<usize>().unwrap()-1];
-                        if items.len() == 3
-                        {
-                            face_normals[i] = normals[items[2].parse::<usize>().unwrap()-1];
-                        }
-                        else 
-                        {
-                            hasNormals = false;
-                        } 
-                    }
-                    if !hasNormals
-                    {
-                        face_normals = [Vector3::zeros();3];
-                    }
-                    faces.push(Face::new(faces.len(),face_vertices, face_normals));
-                }
-                _ => continue,
-            }
+            let normals = match triangle.map(|(_, _, n)| n) {
+                [Some(a), Some(b), Some(c)] => [self._normals[a], self._normals[b], self._normals[c]],
+                _ => [Vector3::zeros(); 3],
+            };
+            let mut face = Face::new(self.faces.len(), vertices, normals);
+            face.tex_coords = match triangle.map(|(_, t, _)| t) {
+                [Some(a), Some(b), Some(c)] => Some([self._tex_coords[a], self._tex_coords[b], self._tex_coords[c]]),
+                _ => None,
+            };
+            face.material = material;
+            face.group = group;
+            self.faces.push(face);
         }
+        Ok(())
+    }
 
-        Obj{_vertices: vertices, _normals: normals,faces} 
+    /// Parses face corner: v, v/vt, v//vn or v/vt/vn
+    fn parseCorner(&self, arg: &str) -> Result<FaceCorner, String>
+    {
+        let mut items = arg.split('/');
+        let vertex = parseIndex(items.next().unwrap_or(""), self._vertices.len(), "vertex")?;
+        let tex_coord = match items.next() {
+            Some(item) if !item.is_empty() => Some(parseIndex(item, self._tex_coords.len(), "texture")?),
+            _ => None,
+        };
+        let normal = match items.next() {
+            Some(item) if !item.is_empty() => Some(parseIndex(item, self._normals.len(), "normal")?),
+            _ => None,
+        };
+        Ok((vertex, tex_coord, normal))
     }
 
     /// Finds the coordinates of minimal cuboid that contains all vertices
@@ -147,7 +172,12 @@ pub struct Face
     pub projectMatrix: Matrix3<f32>,
     pub s: Vector3<f32>,
     pub t: Vector3<f32>,
-    pub base: Vector3<f32>
+    pub base: Vector3<f32>,
+    /// index of material in Obj::materials, None if face has no material
+    pub material: Option<usize>,
+    /// index of group in Obj::groups, None if face is outside any group
+    pub group: Option<usize>,
+    pub tex_coords: Option<[Vector2<f32>;3]>,
 }
 
 impl Face {
@@ -181,7 +211,7 @@ impl Face {
         Face{id, _vertices: vertices, _normals: normals, normal: n, has_true_normals,
                 projectMatrix: invProjectMatrix.try_inverse().expect("Can not inverse project matrix"),
                 s, t,
-                base: vertices[0].clone()}
+                base: vertices[0].clone(), material: None, group: None, tex_coords: None}
     }
 
     /// Projects point on face. Return true if projection is inside triangle. 
@@ -197,6 +227,85 @@ impl Face {
         (false, 0.0)
     }
 
+    /// Returns point of triangle closest to specified point. Works also if projection is outside triangle.
+    pub fn closestPoint(&self, point: &Vector3<f32>) -> Vector3<f32>
+    {
+        let [a, b, c] = self._vertices;
+        let ap = point - a;
+        let d1 = self.s.dot(&ap);
+        let d2 = self.t.dot(&ap);
+        if d1 <= 0.0 && d2 <= 0.0
+        {
+            return a;
+        }
+        let bp = point - b;
+        let d3 = self.s.dot(&bp);
+        let d4 = self.t.dot(&bp);
+        if d3 >= 0.0 && d4 <= d3
+        {
+            return b;
+        }
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0
+        {
+            return a + self.s * (d1 / (d1 - d3));
+        }
+        let cp = point - c;
+        let d5 = self.s.dot(&cp);
+        let d6 = self.t.dot(&cp);
+        if d6 >= 0.0 && d5 <= d6
+        {
+            return c;
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0
+        {
+            return a + self.t * (d2 / (d2 - d6));
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0
+        {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+        let denom = 1.0 / (va + vb + vc);
+        a + self.s * (vb * denom) + self.t * (vc * denom)
+    }
+
+    /// Sweeps sphere from center along motion. Returns fraction of motion when sphere touches triangle
+    /// and contact point. Checks triangle interior, edges and vertices. Sphere already touching triangle gives 0.
+    pub fn sweepSphere(&self, center: &Vector3<f32>, motion: &Vector3<f32>, radius: f32) -> Option<(f32, Vector3<f32>)>
+    {
+        let closest = self.closestPoint(center);
+        if (center - closest).norm_squared() <= radius * radius
+        {
+            return Some((0.0, closest));
+        }
+
+        // Interior: sphere touches plane in point that projects inside triangle
+        let dist = self.normal.dot(&(center - self.base));
+        let side = if dist >= 0.0 { 1.0 } else { -1.0 };
+        let approach = self.normal.dot(motion) * side;
+        if approach < 0.0
+        {
+            let time = (dist.abs() - radius) / -approach;
+            if (0.0..=1.0).contains(&time)
+            {
+                let contact = center + motion * time - self.normal * (side * radius);
+                if self.projectPoint(contact).0
+                {
+                    return Some((time, contact));
+                }
+            }
+        }
+
+        // Edges and vertices: the earliest touch wins
+        let [a, b, c] = self._vertices;
+        [(a, b), (b, c), (c, a)].iter()
+            .filter_map(|(from, to)| sweepSphereEdge(center, motion, radius, from, to))
+            .chain(self._vertices.iter().filter_map(|v| sweepSphereVertex(center, motion, radius, v)))
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+    }
+
     // Check if ray comes across triangle. Möller-Trumbore algorithm.
     pub fn rayIntersection (&self, point: Vector3<f32>, dir: Vector3<f32>) -> (bool, f32)
     {
@@ -241,4 +350,191 @@ impl Hash for Face {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.id.hash(state);
     }
-}
\ No newline at end of file
+}
+/// Parses number of statement
+fn parseNumber(value: &str, what: &str) -> Result<f32, String>
+{
+    value.parse().map_err(|_| format!("Invalid {} coordinate: {}", what, value))
+}
+
+/// Parses first three numbers of statement. Further values (w component, vertex colors) are ignored
+fn parseVector(args: &[&str], what: &str) -> Result<Vector3<f32>, String>
+{
+    if args.len() < 3
+    {
+        return Err(format!("{} needs 3 coordinates, got {}", what, args.len()));
+    }
+    Ok(Vector3::new(parseNumber(args[0], what)?, parseNumber(args[1], what)?, parseNumber(args[2], what)?))
+}
+
+/// Resolves index of face corner. Positive indices count from 1, negative ones backwards from the last element defined so far
+fn parseIndex(value: &str, count: usize, what: &str) -> Result<usize, String>
+{
+    let index: isize = value.parse().map_err(|_| format!("Invalid {} index: {}", what, value))?;
+    let resolved = if index > 0 { index - 1 } else { count as isize + index };
+    if index == 0 || resolved < 0 || resolved >= count as isize
+    {
+        return Err(format!("{} index {} out of range, {} defined", what, index, count));
+    }
+    Ok(resolved as usize)
+}
+
+/// Triangulates planar polygon by ear clipping, so concave polygons are split correctly.
+/// Returns triangles as indices of polygon corners and false if polygon could not be clipped
+/// (self-intersecting or degenerate) and the rest of it was triangulated as fan.
+/// Convex polygon gives the same fan as triangulation from the first corner.
+fn triangulate(points: &[Vector3<f32>]) -> (Vec<[usize; 3]>, bool)
+{
+    // Newell's method, works for concave polygons
+    let mut normal = Vector3::zeros();
+    for i in 0..points.len()
+    {
+        normal += points[i].cross(&points[(i + 1) % points.len()]);
+    }
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len().saturating_sub(2));
+    while remaining.len() > 3
+    {
+        let count = remaining.len();
+        let corner = |i: usize| [remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]];
+        match (1..=count).map(|k| k % count).find(|i| isEar(points, &normal, &remaining, corner(*i))) {
+            Some(i) => {
+                triangles.push(corner(i));
+                remaining.remove(i);
+            },
+            None => {
+                triangles.extend((1..count - 1).map(|i| [remaining[0], remaining[i], remaining[i + 1]]));
+                return (triangles, false);
+            }
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    (triangles, true)
+}
+
+/// Checks if triangle of three consecutive polygon corners is convex and contains no other remaining corner
+fn isEar(points: &[Vector3<f32>], normal: &Vector3<f32>, remaining: &[usize], [a, b, c]: [usize; 3]) -> bool
+{
+    let (pa, pb, pc) = (points[a], points[b], points[c]);
+    if (pb - pa).cross(&(pc - pb)).dot(normal) <= 0.0
+    {
+        return false;
+    }
+    !remaining.iter().map(|i| points[*i])
+        .filter(|p| *p != pa && *p != pb && *p != pc)
+        .any(|p| (pb - pa).cross(&(p - pa)).dot(normal) >= 0.0
+            && (pc - pb).cross(&(p - pb)).dot(normal) >= 0.0
+            && (pa - pc).cross(&(p - pc)).dot(normal) >= 0.0)
+}
+
+/// Returns position of name in list, appending it if missing
+fn findOrInsert(list: &mut Vec<String>, name: String) -> usize
+{
+    list.iter().position(|item| *item == name).unwrap_or_else(|| {
+        list.push(name);
+        list.len() - 1
+    })
+}
+
+/// Sweeps sphere against vertex. Returns fraction of motion when sphere touches vertex and the vertex
+fn sweepSphereVertex(center: &Vector3<f32>, motion: &Vector3<f32>, radius: f32, vertex: &Vector3<f32>) -> Option<(f32, Vector3<f32>)>
+{
+    let f = center - vertex;
+    let a = motion.norm_squared();
+    let b = 2.0 * f.dot(motion);
+    let c = f.norm_squared() - radius * radius;
+    let disc = b * b - 4.0 * a * c;
+    if a == 0.0 || disc < 0.0
+    {
+        return None;
+    }
+    let time = (-b - disc.sqrt()) / (2.0 * a);
+    (0.0..=1.0).contains(&time).then_some((time, *vertex))
+}
+
+/// Sweeps sphere against edge without its ends. Returns fraction of motion when sphere touches edge and contact point
+fn sweepSphereEdge(center: &Vector3<f32>, motion: &Vector3<f32>, radius: f32, from: &Vector3<f32>, to: &Vector3<f32>) -> Option<(f32, Vector3<f32>)>
+{
+    let edge = to - from;
+    let length_sq = edge.norm_squared();
+    let offset = center - from;
+    // Remove components along edge, the rest is distance to infinite line
+    let m = offset - edge * (offset.dot(&edge) / length_sq);
+    let d = motion - edge * (motion.dot(&edge) / length_sq);
+    let a = d.norm_squared();
+    let b = 2.0 * m.dot(&d);
+    let c = m.norm_squared() - radius * radius;
+    let disc = b * b - 4.0 * a * c;
+    if a <= f32::EPSILON * length_sq || disc < 0.0
+    {
+        return None;
+    }
+    let time = (-b - disc.sqrt()) / (2.0 * a);
+    if !(0.0..=1.0).contains(&time)
+    {
+        return None;
+    }
+    let along = (offset + motion * time).dot(&edge) / length_sq;
+    (0.0..=1.0).contains(&along).then(|| (time, from + edge * along))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_polygons_relative_indices_and_groups() {
+        let source = "# quad and pentagon\nmtllib walls.mtl\nv 0 0 0 1.0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+            vn 0 0 2\nvt 0.5 0.5 0\nvt 1\ng wall\nusemtl concrete\nf 1//1 2//1 3//1 4//1\n\
+            o roof\nv 0 0 1\nv 1 0 1\nv 2 0.5 1\nv 1 1 1\nv 0 1 1\nf -5/1 -4/2 -3/1 -2/2 -1/1\nf 1 2 2\n";
+        let obj = Obj::parse(source.as_bytes(), false).unwrap();
+        assert_eq!(obj._vertices.len(), 9);
+        assert_eq!(obj._tex_coords, vec![Vector2::new(0.5, 0.5), Vector2::new(1.0, 0.0)]);
+        assert_eq!(obj.faces.len(), 5);
+        assert!(obj.faces[..2].iter().all(|f| f.has_true_normals && f.normal == Vector3::z() && f.group == Some(0) && f.tex_coords.is_none()));
+        assert!(obj.faces[2..].iter().all(|f| !f.has_true_normals && f.group == Some(1) && f.material == Some(0)));
+        assert_eq!(obj.faces[4]._vertices, [Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 1.0, 1.0), Vector3::new(0.0, 1.0, 1.0)]);
+        assert_eq!(obj.faces[2].tex_coords.unwrap()[1], Vector2::new(1.0, 0.0));
+        assert_eq!(obj.groups, vec!["wall", "roof"]);
+
+        assert_eq!(Obj::parse("v 0 0 0\nv 1 0 0\nf 1 2 -3\n".as_bytes(), false).err().unwrap(), "line 3: vertex index -3 out of range, 2 defined");
+        assert_eq!(Obj::parse("v 0 0\n".as_bytes(), false).err().unwrap(), "line 1: vertex needs 3 coordinates, got 2");
+        assert!(Obj::parse("v 0 0 0\nf 1 1\n".as_bytes(), false).is_err());
+        assert!(Obj::from_file("no_such_map.obj").is_err());
+    }
+
+    #[test]
+    fn concave_polygon_is_ear_clipped() {
+        // L shape starting at corner which does not see the whole polygon, fan would stick out of it
+        let source = "v 2 1 0\nv 1 1 0\nv 1 2 0\nv 0 2 0\nv 0 0 0\nv 2 0 0\nf 1 2 3 4 5 6\n";
+        let obj = Obj::parse(source.as_bytes(), false).unwrap();
+        assert_eq!(obj.faces.len(), 4);
+        let area: f32 = obj.faces.iter().map(|f| {
+            let v = f._vertices;
+            let cross = (v[1] - v[0]).cross(&(v[2] - v[0]));
+            assert!(cross.z > 0.0);
+            cross.norm() / 2.0
+        }).sum();
+        assert!((area - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sphere_sweep_hits_face_edge_and_vertex() {
+        let face = Face::new(0, [Vector3::new(0.0, 0.0, 0.0), Vector3::new(4.0, 0.0, 0.0), Vector3::new(0.0, 4.0, 0.0)], [Vector3::zeros(); 3]);
+        // Interior: thin wall crossed in one step
+        let (time, point) = face.sweepSphere(&Vector3::new(1.0, 1.0, 2.0), &Vector3::new(0.0, 0.0, -10.0), 0.5).unwrap();
+        assert!((time - 0.15).abs() < 1e-5);
+        assert!((point - Vector3::new(1.0, 1.0, 0.0)).norm() < 1e-5);
+        // Edge along x axis approached from side
+        let (time, point) = face.sweepSphere(&Vector3::new(2.0, -3.0, 0.0), &Vector3::new(0.0, 4.0, 0.0), 1.0).unwrap();
+        assert!((time - 0.5).abs() < 1e-5);
+        assert!((point - Vector3::new(2.0, 0.0, 0.0)).norm() < 1e-5);
+        // Vertex approached from behind
+        let (time, point) = face.sweepSphere(&Vector3::new(-3.0, -3.0, 0.0), &Vector3::new(4.0, 4.0, 0.0), 2.0f32.sqrt()).unwrap();
+        assert!((time - 0.5).abs() < 1e-5);
+        assert_eq!(point, Vector3::zeros());
+        // Resting contact and miss
+        assert_eq!(face.sweepSphere(&Vector3::new(1.0, 1.0, 0.4), &Vector3::zeros(), 0.5).unwrap().0, 0.0);
+        assert!(face.sweepSphere(&Vector3::new(1.0, 1.0, 2.0), &Vector3::new(0.0, 0.0, 1.0), 0.5).is_none());
+    }
+}
@@ -1,8 +1,9 @@
 use std::{thread::{self, JoinHandle}, sync::{Arc, Mutex, atomic::{Ordering, AtomicBool}}};
 use std::{process::{Command, Stdio}, time::{self, Instant}, collections::HashMap};
 use nalgebra::Vector3;
-use crate::{printLog, config::ServerConfig, notification::Notification, logger};
+use crate::{printLog, printWarn, config::ServerConfig, notification::Notification, logger, recorder::Recorder, simulation::Simulation, atmosphere::AtmosphereInfo};
 use std::io::{BufRead, BufReader};
+use crate::forces::OuterForces;
 
 
 
@@ -15,12 +16,26 @@ pub struct ObjectState
     pub vel: Vector3<f32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Kind of object released by UAV
+pub enum ObjectKind
+{
+    AMMO,
+    CARGO,
+}
+
+#[derive(Debug, Clone)]
 /// Extra information about object from drone configuration file
 pub struct ObjectInfo
 {
     pub model_name: String,
-    pub collision_radius: f32
+    pub collision_radius: f32,
+    /// id of UAV that released object
+    pub owner: usize,
+    pub kind: ObjectKind,
+    pub mass: f32,
+    /// marker ammunition only marks hit aircraft
+    pub marker: bool,
 }
 
 impl ObjectState {
@@ -58,6 +73,8 @@ pub struct Objects
     running: Arc<AtomicBool>,
 
     control_socket: zmq::Socket,
+    /// outer forces set by cargo links and collision impulses per object
+    forces: OuterForces,
     _state_proxy: Option<JoinHandle<()>>,
     _state_cupturer: Option<JoinHandle<()>>,
     _dropListener: Option<(JoinHandle<()>,JoinHandle<()>)>
@@ -67,9 +84,14 @@ impl Objects
 {
     /// Constructor
     pub fn new(_ctx: zmq::Context, port: usize) -> Self {
-        let drop_physic = Command::new("../UAV_drop_physic/build/drop")
-        .arg("--dt").arg(ServerConfig::get_usize("obj_physic_step_time").to_string())
-        .arg("--ode").arg(ServerConfig::get_str("obj_physic_ode_solver"))
+        let mut drop_physic = Command::new("../UAV_drop_physic/build/drop");
+        drop_physic.arg("--dt").arg(ServerConfig::get_usize("obj_physic_step_time").to_string())
+        .arg("--ode").arg(ServerConfig::get_str("obj_physic_ode_solver"));
+        if Simulation::isLockstep()
+        {
+            drop_physic.arg("--lockstep");
+        }
+        let drop_physic = drop_physic
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -99,6 +121,8 @@ impl Objects
         let info_access = info.clone();
         let mut last_notify = Instant::now();
         let notify_period = ServerConfig::get_usize("notify_period").try_into().unwrap();
+        let clock_drift_warn = ServerConfig::get_f32("clock_drift_warn");
+        let mut clock_offset: Option<f32> = None;
         let capture: JoinHandle<()> = thread::spawn(move ||
         {
             let capture_socket = ctx.socket(zmq::SUB).expect("Capture socket error");
@@ -114,10 +138,17 @@ impl Objects
                 }
                 let obj_info = obj_states_msg.as_str().unwrap().to_string();
                 Self::parseInfo(&time_access,&states_access,obj_info);
+                let time = *time_access.lock().unwrap();
+                let offset = *clock_offset.get_or_insert(Simulation::now() - time);
                 if last_notify.elapsed().as_millis() > notify_period
                 {
                     last_notify = Instant::now();
                     sendModelInfo(&info_access);
+                    let lag = Simulation::now() - offset - time;
+                    if lag > clock_drift_warn
+                    {
+                        printWarn!("Drop physic: simulation time lags master clock by {:.3} s", lag);
+                    }
                 }
             }
         });
@@ -141,11 +172,12 @@ impl Objects
             {
                 if let Ok(content) = line
                 {
-                    logger::Logger::print("drop", "physic", logger::COLOR_RED, &content);
+                    logger::Logger::printLevel(logger::LogLevel::WARN, "drop", "physic", logger::COLOR_RED, &content);
                 }
             }
         }));
         Objects {_ctx: _ctx,_time: time,states: states,info, running: running, control_socket: control_socket,
+            forces: OuterForces::default(),
             _state_proxy: Some(proxy), _state_cupturer: Some(capture), _dropListener: Some(listener)}
     }
 
@@ -153,6 +185,9 @@ impl Objects
     fn parseInfo(time: &Arc<Mutex<f32>>, states: &Arc<Mutex<Vec<ObjectState>>>, info: String)
     {
         let mut newStates = Vec::new();
+        let mut obj_time = 0.0;
+        // States are tagged with master clock and wall time of reception
+        let (sim_time, wall_time) = (Simulation::now(), Simulation::wallTime());
         let list = info.split(";");
         for (i,elem) in list.into_iter().enumerate()
         {
@@ -164,11 +199,16 @@ impl Objects
             {
                 let mut time_lck = time.lock().unwrap();
                 *time_lck = elem.parse::<f32>().unwrap();
+                obj_time = *time_lck;
                 drop(time_lck);
                 continue;
             }
             newStates.push(ObjectState::fromInfo(elem));
         }
+        for state in newStates.iter()
+        {
+            Recorder::recordObject(obj_time, sim_time, wall_time, state.id, &state.pos, &state.vel);
+        }
         let mut state_lck = states.lock().unwrap();
         *state_lck = newStates;
         drop(state_lck);
@@ -218,37 +258,70 @@ impl Objects
     }
 
     /// Remove object from simulation
-    pub fn removeObj(&self, id: usize)
+    pub fn removeObj(&mut self, id: usize)
     {
         self._sendControlMsg(&format!("r:{}",id.to_string()));
+        self.forces.remove(id);
         if let Ok(mut info) = self.info.lock()
         {
             info.remove(&id);
         }
     }
 
-    /// Peridically updates winds info for objects
-    pub fn updateWind(&self, wind: Vec<(usize,Vector3<f32>)>)
+    /// Peridically updates atmosphere info for objects:
+    /// w:ID,WIND_X,WIND_Y,WIND_Z,TEMPERATURE,PRESSURE,DENSITY;...
+    pub fn updateAtmosphere(&self, infos: &[(usize,AtmosphereInfo)])
     {
-        let mut command = String::with_capacity(30*wind.len());
+        let mut command = String::with_capacity(60*infos.len());
         command.push_str("w:");
-        for (id,wind_vec) in wind {
+        for (id,info) in infos {
             command.push_str(&id.to_string());
             command.push(',');
-            command.push_str(&wind_vec[0].to_string());
+            command.push_str(&info.wind[0].to_string());
             command.push(',');
-            command.push_str(&wind_vec[1].to_string());
+            command.push_str(&info.wind[1].to_string());
             command.push(',');
-            command.push_str(&wind_vec[2].to_string());
+            command.push_str(&info.wind[2].to_string());
+            command.push(',');
+            command.push_str(&info.air_temperature.to_string());
+            command.push(',');
+            command.push_str(&info.air_pressure.to_string());
+            command.push(',');
+            command.push_str(&info.air_density.to_string());
             command.push(';');
         }
         self._sendControlMsg(&command);
     }
 
 
-    /// Sets outer force value applied to object specified by id
-    pub fn setForce(&self,id: usize, force: Vector3<f32>)
+    /// Sets outer force value applied to object specified by id. Active collision impulses are added to it
+    pub fn setForce(&mut self,id: usize, force: Vector3<f32>)
+    {
+        self.forces.set(id, &force, &Vector3::zeros());
+        self.sendForce(id);
+    }
+
+    /// Applies impulse to object specified by id. Impulse is sent as constant force
+    /// lasting duration in s of simulation time, added to outer force
+    pub fn applyImpulse(&mut self, id: usize, impulse: &Vector3<f32>, duration: f32)
     {
+        self.forces.addImpulse(id, impulse, &Vector3::zeros(), duration);
+        self.sendForce(id);
+    }
+
+    /// Removes finished collision impulses and restores outer force of their objects
+    pub fn expireImpulses(&mut self)
+    {
+        for id in self.forces.expire()
+        {
+            self.sendForce(id);
+        }
+    }
+
+    /// Sends sum of outer force and active impulse force to object specified by id
+    fn sendForce(&self, id: usize)
+    {
+        let (force, _) = self.forces.total(id);
         let mut command = String::with_capacity(30);
         command.push_str("f:");
         command.push_str(&id.to_string());
@@ -285,6 +358,18 @@ impl Objects
         self._sendControlMsg(&command);
     }
 
+    /// Sends simulation pause and time scale to object's simulation
+    pub fn sendSimulationState(&self, msg: &str)
+    {
+        self._sendControlMsg(msg);
+    }
+
+    /// Sends command to advance object's simulation by specified step in ms. Used in lockstep mode
+    pub fn sendStep(&self, step_ms: usize)
+    {
+        self._sendControlMsg(&format!("n:{}", step_ms));
+    }
+
     /// Get position of all objects in air
     pub fn getPositions(&self) -> Vec<(usize,Vector3<f32>)>
     {
@@ -352,6 +437,12 @@ impl Objects
         drop(info);
         posvel
     }
+
+    /// Get extra information about object specified by id
+    pub fn getInfo(&self, id: usize) -> Option<ObjectInfo>
+    {
+        self.info.lock().unwrap().get(&id).cloned()
+    }
 }
 
 /// Notifies subsribers about type of objects in air
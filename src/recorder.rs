@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::fs::{File, create_dir_all, read, read_dir};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::sync::Mutex;
+use std::time::Instant;
+use nalgebra::Vector3;
+use crate::logger::Logger;
+use crate::simulation::Simulation;
+use crate::{printLog, printError};
+
+/// How often buffered records are flushed to disk in ms
+const FLUSH_PERIOD: u128 = 1000;
+/// Magic bytes at the beginning of binary recording. Last byte is format version
+pub const BINARY_MAGIC: &[u8; 8] = b"UAVREC\x00\x01";
+/// Name of binary recording file in session folder
+pub const BINARY_FILE: &str = "telemetry.bin";
+
+/// static recorder state. None if recorder is disabled or not initialized
+static RECORDER: Mutex<Option<RecorderState>> = Mutex::new(None);
+
+/// Records full simulation history to session folder.
+/// Contains static methods to record samples and not require class instance to use.
+///
+/// CSV format (recorder_format: csv) writes one file per stream, first line is header.
+/// Every row starts with `time` (simulation time in s) and `wall_time` (s since server start).
+/// Drones and objects use time reported by their physic process and are tagged with `sim_time` (master clock)
+/// and `wall_time` when state was received, other streams use master simulation clock:
+/// - `<drone name>_<id>/telemetry.csv` (name characters other than `[A-Za-z0-9_-]` replaced by `_`): time,wall_time,id,sim_time,x,y,z,q0,qx,qy,qz,vx,vy,vz,wx,wy,wz,ax,ay,az,epsx,epsy,epsz,om...
+///   (om -- variable number of rotor angular velocities)
+/// - `objects.csv`: time,wall_time,id,sim_time,x,y,z,vx,vy,vz
+/// - `atmosphere.csv`: time,wall_time,target,id,wind_x,wind_y,wind_z,temperature,pressure,density
+///   (target -- drone or object)
+/// - `links.csv`: time,wall_time,drone_id,obj_id,fx,fy,fz,tx,ty,tz (force applied to drone)
+/// - `collisions.csv`: time,wall_time,kind,id,other_id,x,y,z,nx,ny,nz (missing values are NaN)
+/// - `notifications.csv`: time,wall_time,message
+///
+/// Binary format (recorder_format: binary) writes all streams to single `telemetry.bin`.
+/// File starts with BINARY_MAGIC followed by records. All numbers are little endian:
+/// `kind: u8, time: f32, wall_time: f32, label_len: u16, label: utf8, values_len: u16, values: f32 * values_len`.
+/// Values are the CSV columns after wall_time, label is drone name, atmosphere target,
+/// collision kind or notification message depending on kind.
+pub struct Recorder
+{
+
+}
+
+/// Type of recorded sample
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordKind
+{
+    DRONE = 0,
+    OBJECT = 1,
+    ATMOSPHERE = 2,
+    LINK = 3,
+    COLLISION = 4,
+    NOTIFICATION = 5,
+}
+
+impl RecordKind {
+    /// Converts identifier used in binary format to kind
+    pub fn from_u8(value: u8) -> Option<Self>
+    {
+        match value {
+            0 => Some(RecordKind::DRONE),
+            1 => Some(RecordKind::OBJECT),
+            2 => Some(RecordKind::ATMOSPHERE),
+            3 => Some(RecordKind::LINK),
+            4 => Some(RecordKind::COLLISION),
+            5 => Some(RecordKind::NOTIFICATION),
+            _ => None,
+        }
+    }
+
+    /// Returns CSV header of stream
+    fn header(&self) -> &'static str
+    {
+        match self {
+            RecordKind::DRONE => "time,wall_time,id,sim_time,x,y,z,q0,qx,qy,qz,vx,vy,vz,wx,wy,wz,ax,ay,az,epsx,epsy,epsz,om...",
+            RecordKind::OBJECT => "time,wall_time,id,sim_time,x,y,z,vx,vy,vz",
+            RecordKind::ATMOSPHERE => "time,wall_time,target,id,wind_x,wind_y,wind_z,temperature,pressure,density",
+            RecordKind::LINK => "time,wall_time,drone_id,obj_id,fx,fy,fz,tx,ty,tz",
+            RecordKind::COLLISION => "time,wall_time,kind,id,other_id,x,y,z,nx,ny,nz",
+            RecordKind::NOTIFICATION => "time,wall_time,message",
+        }
+    }
+
+    /// Returns true if label is written as column after wall_time in CSV
+    fn labelInRow(&self) -> bool
+    {
+        matches!(self, RecordKind::ATMOSPHERE | RecordKind::COLLISION | RecordKind::NOTIFICATION)
+    }
+}
+
+/// Single recorded sample
+#[derive(Clone, Debug)]
+pub struct Record
+{
+    pub kind: RecordKind,
+    /// simulation time in s
+    pub time: f32,
+    /// time since server start in s
+    pub wall_time: f32,
+    /// drone name, atmosphere target, collision kind or notification message
+    pub label: String,
+    pub values: Vec<f32>,
+}
+
+impl Record {
+    /// Serializes record to CSV row
+    pub fn toCsv(&self) -> String
+    {
+        let mut row = String::with_capacity(20 + 12 * self.values.len() + self.label.len());
+        row.push_str(&self.time.to_string());
+        row.push(',');
+        row.push_str(&self.wall_time.to_string());
+        if self.kind.labelInRow()
+        {
+            row.push(',');
+            row.push_str(&escapeCsv(&self.label));
+        }
+        for value in &self.values
+        {
+            row.push(',');
+            row.push_str(&value.to_string());
+        }
+        row
+    }
+
+    /// Serializes record to binary format
+    pub fn toBytes(&self) -> Vec<u8>
+    {
+        let label = &self.label.as_bytes()[..self.label.len().min(u16::MAX as usize)];
+        let values = &self.values[..self.values.len().min(u16::MAX as usize)];
+        let mut bytes = Vec::with_capacity(13 + label.len() + 4 * values.len());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.time.to_le_bytes());
+        bytes.extend_from_slice(&self.wall_time.to_le_bytes());
+        bytes.extend_from_slice(&(label.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(label);
+        bytes.extend_from_slice(&(values.len() as u16).to_le_bytes());
+        for value in values
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parses single record from binary format. Returns record and number of consumed bytes
+    pub fn fromBytes(bytes: &[u8]) -> Option<(Record, usize)>
+    {
+        let kind = RecordKind::from_u8(*bytes.first()?)?;
+        let time = f32::from_le_bytes(bytes.get(1..5)?.try_into().ok()?);
+        let wall_time = f32::from_le_bytes(bytes.get(5..9)?.try_into().ok()?);
+        let label_len = u16::from_le_bytes(bytes.get(9..11)?.try_into().ok()?) as usize;
+        let label = String::from_utf8_lossy(bytes.get(11..11 + label_len)?).to_string();
+        let mut offset = 11 + label_len;
+        let values_len = u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?) as usize;
+        offset += 2;
+        let mut values = Vec::with_capacity(values_len);
+        for _ in 0..values_len
+        {
+            values.push(f32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?));
+            offset += 4;
+        }
+        Some((Record { kind, time, wall_time, label, values }, offset))
+    }
+}
+
+/// Splits CSV row into fields. Handles quoted fields
+fn splitCsv(row: &str) -> Vec<String>
+{
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next()
+    {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Returns folder name of drone telemetry. Drone name comes from client, so it is never used as path directly
+fn droneFolder(name: &str, id: usize) -> String
+{
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}_{}", name, id)
+}
+
+/// Drone name from its telemetry folder, inverse of droneFolder up to sanitized characters
+fn droneName(folder: &str) -> &str
+{
+    match folder.rsplit_once('_') {
+        Some((name, id)) if id.parse::<usize>().is_ok() => name,
+        _ => folder
+    }
+}
+
+/// Reads records of single kind from CSV file. Label is used when it is not stored in rows
+fn loadCsv(path: &std::path::Path, kind: RecordKind, label: &str, records: &mut Vec<Record>) -> std::io::Result<()>
+{
+    let reader = BufReader::new(File::open(path)?);
+    for (line_no, line) in reader.lines().enumerate().skip(1)
+    {
+        let line = line?;
+        if line.trim().is_empty()
+        {
+            continue;
+        }
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("{}:{}: invalid row", path.display(), line_no + 1));
+        let fields = splitCsv(&line);
+        let first_value = if kind.labelInRow() { 3 } else { 2 };
+        if fields.len() < first_value
+        {
+            return Err(invalid());
+        }
+        let time = fields[0].parse().map_err(|_| invalid())?;
+        let wall_time = fields[1].parse().map_err(|_| invalid())?;
+        let label = if kind.labelInRow() { fields[2].clone() } else { label.to_string() };
+        let values = fields[first_value..].iter().map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>().map_err(|_| invalid())?;
+        records.push(Record { kind, time, wall_time, label, values });
+    }
+    Ok(())
+}
+
+/// Escapes CSV field if it contains separator, quote or new line
+fn escapeCsv(field: &str) -> String
+{
+    if field.contains([',', '"', '\n'])
+    {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else
+    {
+        field.to_string()
+    }
+}
+
+/// Output of recorder
+enum RecorderOutput
+{
+    /// opened CSV files by path relative to session folder
+    Csv(HashMap<String, BufWriter<File>>),
+    Binary(BufWriter<File>),
+}
+
+/// Internal recorder state
+struct RecorderState
+{
+    folder: String,
+    output: RecorderOutput,
+    last_flush: Instant,
+}
+
+impl RecorderState {
+    /// Writes record to output
+    fn write(&mut self, record: &Record) -> std::io::Result<()>
+    {
+        match &mut self.output {
+            RecorderOutput::Binary(file) => file.write_all(&record.toBytes())?,
+            RecorderOutput::Csv(files) => {
+                let path = match record.kind {
+                    RecordKind::DRONE => format!("{}/telemetry.csv", droneFolder(&record.label, record.values[0] as usize)),
+                    RecordKind::OBJECT => "objects.csv".to_string(),
+                    RecordKind::ATMOSPHERE => "atmosphere.csv".to_string(),
+                    RecordKind::LINK => "links.csv".to_string(),
+                    RecordKind::COLLISION => "collisions.csv".to_string(),
+                    RecordKind::NOTIFICATION => "notifications.csv".to_string(),
+                };
+                if !files.contains_key(&path)
+                {
+                    let full_path = self.folder.clone() + path.as_str();
+                    if let Some((dir, _)) = full_path.rsplit_once('/')
+                    {
+                        create_dir_all(dir)?;
+                    }
+                    let mut file = BufWriter::new(File::create(&full_path)?);
+                    writeln!(file, "{}", record.kind.header())?;
+                    files.insert(path.clone(), file);
+                }
+                writeln!(files.get_mut(&path).unwrap(), "{}", record.toCsv())?;
+            }
+        }
+        if self.last_flush.elapsed().as_millis() > FLUSH_PERIOD
+        {
+            self.last_flush = Instant::now();
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes all buffered records to disk
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        match &mut self.output {
+            RecorderOutput::Binary(file) => file.flush(),
+            RecorderOutput::Csv(files) => files.values_mut().try_for_each(|file| file.flush()),
+        }
+    }
+}
+
+impl Recorder
+{
+    /// Initialize recorder. Must be called after log session is started.
+    /// Format is csv or binary.
+    pub fn init(format: &str)
+    {
+        let folder = Logger::sessionFolder();
+        if folder.is_empty()
+        {
+            printError!("Recorder requires started log session. Recording disabled");
+            return;
+        }
+        let output = match format {
+            "csv" => RecorderOutput::Csv(HashMap::new()),
+            "binary" => {
+                let mut file = BufWriter::new(File::create(folder.clone() + BINARY_FILE).expect("Unable to create recording file"));
+                file.write_all(BINARY_MAGIC).expect("Unable to write recording file");
+                RecorderOutput::Binary(file)
+            }
+            other => panic!("Unknown recorder format: {}", other),
+        };
+        let mut recorder_lck = RECORDER.lock().unwrap();
+        *recorder_lck = Some(RecorderState { folder, output, last_flush: Instant::now() });
+        printLog!("Recorder started. Format: {}", format);
+    }
+
+    /// Flushes and closes recording
+    pub fn close()
+    {
+        let mut recorder_lck = RECORDER.lock().unwrap();
+        if let Some(mut state) = recorder_lck.take()
+        {
+            if let Err(e) = state.flush()
+            {
+                printError!("Unable to flush recording: {}", e);
+            }
+        }
+    }
+
+    /// Records sample tagged with current wall time. Does nothing if recorder is disabled
+    pub fn record(kind: RecordKind, time: f32, label: &str, values: Vec<f32>)
+    {
+        Self::recordAt(kind, time, Simulation::wallTime(), label, values);
+    }
+
+    /// Records sample tagged with specified wall time
+    fn recordAt(kind: RecordKind, time: f32, wall_time: f32, label: &str, values: Vec<f32>)
+    {
+        let mut recorder_lck = RECORDER.lock().unwrap();
+        if let Some(state) = recorder_lck.as_mut()
+        {
+            let record = Record { kind, time, wall_time, label: label.to_string(), values };
+            if let Err(e) = state.write(&record)
+            {
+                printError!("Unable to write recording: {}. Recording disabled", e);
+                *recorder_lck = None;
+            }
+        }
+    }
+
+    /// Loads recording from session folder. Binary recording is used if present, otherwise CSV files.
+    /// Records are sorted by wall time.
+    pub fn load(folder: &str) -> std::io::Result<Vec<Record>>
+    {
+        let folder = std::path::Path::new(folder);
+        let mut records = Vec::new();
+        let binary_path = folder.join(BINARY_FILE);
+        if binary_path.exists()
+        {
+            let bytes = read(&binary_path)?;
+            if !bytes.starts_with(BINARY_MAGIC)
+            {
+                return Err(Error::new(ErrorKind::InvalidData, "Unknown recording format"));
+            }
+            let mut offset = BINARY_MAGIC.len();
+            while offset < bytes.len()
+            {
+                match Record::fromBytes(&bytes[offset..]) {
+                    Some((record, consumed)) => {
+                        records.push(record);
+                        offset += consumed;
+                    }
+                    None => {
+                        printError!("Recording truncated at byte {}", offset);
+                        break;
+                    }
+                }
+            }
+        }
+        else
+        {
+            let streams = [("objects.csv", RecordKind::OBJECT), ("atmosphere.csv", RecordKind::ATMOSPHERE),
+                ("links.csv", RecordKind::LINK), ("collisions.csv", RecordKind::COLLISION),
+                ("notifications.csv", RecordKind::NOTIFICATION)];
+            for (file_name, kind) in streams
+            {
+                let path = folder.join(file_name);
+                if path.exists()
+                {
+                    loadCsv(&path, kind, "", &mut records)?;
+                }
+            }
+            for entry in read_dir(folder)?
+            {
+                let entry = entry?;
+                let path = entry.path().join("telemetry.csv");
+                if path.exists()
+                {
+                    let folder_name = entry.file_name().to_string_lossy().into_owned();
+                    loadCsv(&path, RecordKind::DRONE, droneName(&folder_name), &mut records)?;
+                }
+            }
+        }
+        records.sort_by(|a, b| a.wall_time.total_cmp(&b.wall_time));
+        Ok(records)
+    }
+
+    /// Records drone state. Values are in order described in Recorder documentation
+    pub fn recordDrone(name: &str, id: usize, time: f32, sim_time: f32, wall_time: f32, telemetry: &[f32])
+    {
+        let mut values = Vec::with_capacity(telemetry.len() + 2);
+        values.push(id as f32);
+        values.push(sim_time);
+        values.extend_from_slice(telemetry);
+        Self::recordAt(RecordKind::DRONE, time, wall_time, name, values);
+    }
+
+    /// Records object state
+    pub fn recordObject(time: f32, sim_time: f32, wall_time: f32, id: usize, pos: &Vector3<f32>, vel: &Vector3<f32>)
+    {
+        Self::recordAt(RecordKind::OBJECT, time, wall_time, "",
+            vec![id as f32, sim_time, pos.x, pos.y, pos.z, vel.x, vel.y, vel.z]);
+    }
+
+    /// Records atmosphere sample sent to drone or object
+    pub fn recordAtmosphere(target: &str, id: usize, wind: &Vector3<f32>, temperature: f32, pressure: f32, density: f32)
+    {
+        Self::record(RecordKind::ATMOSPHERE, Simulation::now(), target,
+            vec![id as f32, wind.x, wind.y, wind.z, temperature, pressure, density]);
+    }
+
+    /// Records force and torque applied by link to drone
+    pub fn recordLink(drone_id: usize, obj_id: usize, force: &Vector3<f32>, torque: &Vector3<f32>)
+    {
+        Self::record(RecordKind::LINK, Simulation::now(), "",
+            vec![drone_id as f32, obj_id as f32, force.x, force.y, force.z, torque.x, torque.y, torque.z]);
+    }
+
+    /// Records collision or boundary event. Missing values should be NaN
+    pub fn recordCollision(kind: &str, id: usize, other_id: Option<usize>, point: &Vector3<f32>, normal: &Vector3<f32>)
+    {
+        Self::record(RecordKind::COLLISION, Simulation::now(), kind,
+            vec![id as f32, other_id.map_or(f32::NAN, |o| o as f32), point.x, point.y, point.z, normal.x, normal.y, normal.z]);
+    }
+
+    /// Records notification message
+    pub fn recordNotification(msg: &str)
+    {
+        Self::record(RecordKind::NOTIFICATION, Simulation::now(), msg, Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_record_roundtrip() {
+        let record = Record { kind: RecordKind::COLLISION, time: 1.5, wall_time: 2.25,
+            label: "terrain".to_string(), values: vec![1.0, f32::NAN, -3.5] };
+        let mut bytes = record.toBytes();
+        bytes.extend_from_slice(&[0xFF, 0xFF]);
+        let (parsed, consumed) = Record::fromBytes(&bytes).unwrap();
+        assert_eq!(consumed, bytes.len() - 2);
+        assert_eq!(parsed.kind, RecordKind::COLLISION);
+        assert_eq!(parsed.time, 1.5);
+        assert_eq!(parsed.label, "terrain");
+        assert_eq!(parsed.values[0], 1.0);
+        assert!(parsed.values[1].is_nan());
+        assert!(Record::fromBytes(&bytes[..consumed - 1]).is_none());
+    }
+
+    #[test]
+    fn csv_row_escapes_label() {
+        let record = Record { kind: RecordKind::NOTIFICATION, time: 1.0, wall_time: 2.0,
+            label: "p:1,2,FF0000,0,HIT;".to_string(), values: Vec::new() };
+        assert_eq!(record.toCsv(), "1,2,\"p:1,2,FF0000,0,HIT;\"");
+        let record = Record { kind: RecordKind::OBJECT, time: 1.0, wall_time: 2.0,
+            label: String::new(), values: vec![3.0, 0.5] };
+        assert_eq!(record.toCsv(), "1,2,3,0.5");
+    }
+
+    #[test]
+    fn drone_folder_is_sanitized() {
+        assert_eq!(droneFolder("../../etc/x y", 3), "______etc_x_y_3");
+        assert_eq!(droneFolder("uav-1_a", 12), "uav-1_a_12");
+        assert_eq!(droneName("uav-1_a_12"), "uav-1_a");
+        assert_eq!(droneName("uav"), "uav");
+    }
+
+    #[test]
+    fn csv_row_split_handles_quotes() {
+        assert_eq!(splitCsv("1,2,\"p:1,2,\"\"x\"\";\""), vec!["1", "2", "p:1,2,\"x\";"]);
+        assert_eq!(splitCsv("1,,NaN"), vec!["1", "", "NaN"]);
+    }
+}
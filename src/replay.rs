@@ -0,0 +1,273 @@
+use std::{thread::{JoinHandle, self}, sync::{Arc, atomic::{AtomicBool, Ordering}}, collections::BTreeMap, time::{self, Instant}};
+use serde_json::json;
+use crate::{recorder::{Recorder, Record, RecordKind}, notification::Notification, config::ServerConfig, checksum::getChecksum};
+use crate::{printLog, printWarn};
+
+/// Time after which drone or object without new samples is not published anymore in s
+const STALE_TIME: f32 = 0.5;
+/// Period of publishing states in ms
+const PUBLISH_PERIOD: u64 = 10;
+
+/// Replays recorded session. Publishes recorded drones states, objects states and notifications
+/// on the same ports and in the same formats as running simulation. No physic process is spawned.
+/// Playback is controlled through replyer socket:
+/// r:play, r:pause, r:seek;TIME, r:speed;SPEED, r:status.
+pub struct Replay
+{
+    running: Arc<AtomicBool>,
+    player: Option<thread::JoinHandle<()>>
+}
+
+/// Playback state
+struct Playback
+{
+    records: Vec<Record>,
+    /// index of first not yet played record
+    cursor: usize,
+    /// playback position in recording wall time
+    time: f32,
+    speed: f32,
+    paused: bool,
+    /// last state of every drone by id
+    drones: BTreeMap<usize, Record>,
+    /// objects from the last objects frame by id
+    objects: BTreeMap<usize, Record>,
+    /// wall time of the last objects frame
+    objects_time: f32,
+}
+
+impl Playback {
+    /// Constructor
+    fn new(records: Vec<Record>) -> Self
+    {
+        let time = records.first().map_or(0.0, |r| r.wall_time);
+        Playback { records, cursor: 0, time, speed: 1.0, paused: false,
+            drones: BTreeMap::new(), objects: BTreeMap::new(), objects_time: f32::MIN }
+    }
+
+    /// Returns wall time of the last record
+    fn duration(&self) -> f32
+    {
+        self.records.last().map_or(0.0, |r| r.wall_time)
+    }
+
+    /// Applies all records up to playback time. Notifications are sent only if notify is true
+    fn advance(&mut self, notify: bool)
+    {
+        while let Some(record) = self.records.get(self.cursor)
+        {
+            if record.wall_time > self.time
+            {
+                break;
+            }
+            match record.kind {
+                RecordKind::DRONE => {
+                    self.drones.insert(record.values[0] as usize, record.clone());
+                }
+                RecordKind::OBJECT => {
+                    if record.wall_time != self.objects_time
+                    {
+                        self.objects.clear();
+                        self.objects_time = record.wall_time;
+                    }
+                    self.objects.insert(record.values[0] as usize, record.clone());
+                }
+                RecordKind::NOTIFICATION if notify => Notification::sendMsg(&record.label),
+                _ => {}
+            }
+            self.cursor += 1;
+        }
+    }
+
+    /// Moves playback to specified time. State is rebuilt from the beginning of recording
+    fn seek(&mut self, time: f32)
+    {
+        self.time = time.clamp(0.0, self.duration());
+        self.cursor = 0;
+        self.drones.clear();
+        self.objects.clear();
+        self.objects_time = f32::MIN;
+        self.advance(false);
+    }
+
+    /// Serializes drones states in format of drones state publisher
+    fn dronesMsg(&self) -> String
+    {
+        let mut result = String::with_capacity(self.drones.len()*320);
+        for (id, record) in self.drones.iter().filter(|(_, r)| self.time - r.wall_time < STALE_TIME)
+        {
+            // values: id, sim_time, pos(7), vel(6), acc(6), om...
+            result.push_str(&id.to_string());
+            result.push(',');
+            result.push_str(&record.time.to_string());
+            for value in record.values[2..15].iter().chain(record.values.iter().skip(21)).chain([record.values[1], record.wall_time].iter())
+            {
+                result.push(',');
+                result.push_str(&value.to_string());
+            }
+            result.push(';');
+        }
+        if result.is_empty()
+        {
+            result.push(';');
+        }
+        result
+    }
+
+    /// Serializes objects states in format of drop physic state publisher
+    fn objectsMsg(&self) -> Option<String>
+    {
+        if self.objects.is_empty() || self.time - self.objects_time > STALE_TIME
+        {
+            return None;
+        }
+        let mut result = String::with_capacity(20 + self.objects.len()*80);
+        result.push_str(&self.objects.values().next().unwrap().time.to_string());
+        result.push(';');
+        for record in self.objects.values()
+        {
+            // values: id, sim_time, pos(3), vel(3)
+            result.push_str(&(record.values[0] as usize).to_string());
+            for value in record.values[2..8].iter()
+            {
+                result.push(',');
+                result.push_str(&value.to_string());
+            }
+            result.push(';');
+        }
+        Some(result)
+    }
+
+    /// Handles playback control command. Returns reply
+    fn handleCommand(&mut self, request: &str) -> String
+    {
+        let mut command = request.splitn(2, ';');
+        let action = command.next().unwrap();
+        let param = command.next().and_then(|p| p.trim().parse::<f32>().ok());
+        match (action, param) {
+            ("r:play", _) => self.paused = false,
+            ("r:pause", _) => self.paused = true,
+            ("r:seek", Some(time)) => self.seek(time),
+            ("r:speed", Some(speed)) if speed > 0.0 => self.speed = speed,
+            ("r:status", _) => {}
+            _ => {
+                printWarn!("Unknown replay command: {}", request);
+                return "error".to_string();
+            }
+        }
+        json!({
+            "time": self.time,
+            "duration": self.duration(),
+            "speed": self.speed,
+            "paused": self.paused
+        }).to_string()
+    }
+}
+
+impl Replay
+{
+    /// Constructor. Loads recording from session folder and starts playback
+    pub fn new(ctx: zmq::Context, folder: &str) -> Self
+    {
+        let records = Recorder::load(folder).expect("Unable to load recording");
+        printLog!("Loaded {} records from {}", records.len(), folder);
+        let drones_port = ServerConfig::get_usize("drones_port");
+        let object_port = ServerConfig::get_usize("object_port");
+        let replyer_port = ServerConfig::get_usize("replyer_port");
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let player: JoinHandle<()> = thread::spawn(move ||
+        {
+            let drones_socket = ctx.socket(zmq::PUB).expect("Pub socket error");
+            drones_socket.bind(format!("tcp://*:{}",drones_port).as_str()).unwrap_or_else(|_| panic!("Bind error tcp {}",drones_port));
+            let objects_socket = ctx.socket(zmq::PUB).expect("Pub socket error");
+            objects_socket.bind(format!("tcp://*:{}",object_port).as_str()).unwrap_or_else(|_| panic!("Bind error tcp {}",object_port));
+            let replyer_socket = ctx.socket(zmq::REP).expect("REP socket error");
+            replyer_socket.bind(format!("tcp://*:{}",replyer_port).as_str()).unwrap_or_else(|_| panic!("Bind error tcp {}",replyer_port));
+            printLog!("Replay started. Drones on TCP: {}, objects on TCP: {}, control on TCP: {}", drones_port, object_port, replyer_port);
+
+            let mut playback = Playback::new(records);
+            let mut last_step = Instant::now();
+            while r.load(Ordering::SeqCst) {
+                let mut request = zmq::Message::new();
+                if replyer_socket.recv(&mut request, zmq::DONTWAIT).is_ok()
+                {
+                    let request = request.as_str().unwrap_or("").to_string();
+                    let reply = match request.chars().next() {
+                        Some('i') => json!({
+                            "checksum": getChecksum(),
+                            "map": ServerConfig::get_str("map"),
+                            "configs": Vec::<String>::new(),
+                            "replay": true
+                        }).to_string(),
+                        Some('n') => serde_json::to_string(&Notification::getSnapshot()).unwrap(),
+                        Some('r') => playback.handleCommand(&request),
+                        _ => "error".to_string(),
+                    };
+                    replyer_socket.send(&reply, 0).unwrap();
+                }
+
+                let dt = last_step.elapsed().as_secs_f32();
+                last_step = Instant::now();
+                if !playback.paused
+                {
+                    playback.time += dt * playback.speed;
+                    playback.advance(true);
+                    if playback.time > playback.duration()
+                    {
+                        playback.paused = true;
+                        printLog!("Replay finished");
+                    }
+                }
+                drones_socket.send(&playback.dronesMsg(), 0).unwrap();
+                if let Some(msg) = playback.objectsMsg()
+                {
+                    objects_socket.send(&msg, 0).unwrap();
+                }
+                thread::sleep(time::Duration::from_millis(PUBLISH_PERIOD));
+            }
+        });
+        Replay { running, player: Some(player) }
+    }
+}
+
+/// Deconstructor
+impl Drop for Replay{
+    fn drop(&mut self) {
+        printLog!("Dropping replay instance");
+        self.running.store(false, Ordering::SeqCst);
+        self.player.take().unwrap().join().expect("Join error");
+        printLog!("Replay instance dropped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(kind: RecordKind, wall_time: f32, values: Vec<f32>) -> Record
+    {
+        Record { kind, time: wall_time + 100.0, wall_time, label: "alpha".to_string(), values }
+    }
+
+    #[test]
+    fn playback_publishes_states_in_original_format() {
+        let mut drone = vec![1.0, 90.0];
+        drone.extend((1..=19).map(|v| v as f32));
+        drone.extend([500.0, 501.0]);
+        let records = vec![
+            record(RecordKind::DRONE, 0.0, drone),
+            record(RecordKind::OBJECT, 0.0, vec![7.0, 90.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+            record(RecordKind::OBJECT, 2.0, vec![8.0, 92.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        ];
+        let mut playback = Playback::new(records);
+        playback.advance(false);
+        assert_eq!(playback.dronesMsg(), "1,100,1,2,3,4,5,6,7,8,9,10,11,12,13,500,501,90,0;");
+        assert_eq!(playback.objectsMsg().unwrap(), "100;7,1,2,3,4,5,6;");
+        playback.seek(1.0);
+        assert_eq!(playback.dronesMsg(), ";");
+        assert!(playback.objectsMsg().is_none());
+        playback.seek(2.0);
+        assert_eq!(playback.objectsMsg().unwrap(), "102;8,1,2,3,4,5,6;");
+    }
+}
@@ -0,0 +1,268 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, self};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::{printLog, printError, config::ServerConfig};
+
+/// static pause flag of whole simulation
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// static time scale of simulation stored as f32 bits
+static TIME_SCALE: AtomicU32 = AtomicU32::new(f32::to_bits(1.0));
+/// static master simulation clock
+static CLOCK: Mutex<Clock> = Mutex::new(Clock { base_sim_time: 0.0, base_wall: None, start: None });
+/// static seed of random generators, resolved on first use
+static SEED: Mutex<Option<u64>> = Mutex::new(None);
+/// static lockstep mode flag
+static LOCKSTEP: AtomicBool = AtomicBool::new(false);
+/// static phase of current lockstep step
+static PHASE: Mutex<StepPhase> = Mutex::new(StepPhase::IDLE);
+/// static condition variable notified when lockstep phase changes
+static PHASE_CHANGED: Condvar = Condvar::new();
+
+/// How long lockstep driver waits for subsystem to finish its phase before reporting it
+const PHASE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Interval of checking if waiting thread should stop
+const PHASE_POLL: Duration = Duration::from_millis(50);
+
+/// Phase of single lockstep step. Phases are run in declaration order
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StepPhase
+{
+    IDLE,
+    COLLISION,
+    CARGO,
+    ATMOSPHERE,
+}
+
+/// Master simulation clock. Simulation time advances with wall time multiplied by time scale
+/// and stops while simulation is paused.
+struct Clock
+{
+    /// simulation time at base_wall in s
+    base_sim_time: f64,
+    /// wall time of last pause or time scale change
+    base_wall: Option<Instant>,
+    /// wall time of clock start
+    start: Option<Instant>,
+}
+
+impl Clock {
+    /// Returns current simulation time in s
+    fn now(&self) -> f64
+    {
+        match self.base_wall {
+            Some(base_wall) if !Simulation::isPaused() && !Simulation::isLockstep() =>
+                self.base_sim_time + base_wall.elapsed().as_secs_f64() * Simulation::getTimeScale() as f64,
+            _ => self.base_sim_time,
+        }
+    }
+
+    /// Moves base of clock to current moment. Must be called before pause or time scale change
+    fn commit(&mut self)
+    {
+        if self.base_wall.is_some()
+        {
+            self.base_sim_time = self.now();
+            self.base_wall = Some(Instant::now());
+        }
+    }
+}
+
+/// Global simulation state: pause, time scale and master clock.
+/// Contains static methods and not require class instance to use.
+/// Aggregator loops check it to stop applying forces, turbulence and collisions while paused.
+/// Child processes are informed by Drones::sendSimulationState.
+pub struct Simulation
+{
+
+}
+
+impl Simulation
+{
+    /// Starts master simulation clock
+    pub fn start()
+    {
+        let mut clock_lck = CLOCK.lock().unwrap();
+        clock_lck.base_sim_time = 0.0;
+        clock_lck.base_wall = Some(Instant::now());
+        clock_lck.start = clock_lck.base_wall;
+    }
+
+    /// Returns master simulation time in s. Zero if clock is not started
+    pub fn now() -> f32
+    {
+        let clock_lck = CLOCK.lock().unwrap();
+        clock_lck.now() as f32
+    }
+
+    /// Returns wall time since clock start in s. Zero if clock is not started
+    pub fn wallTime() -> f32
+    {
+        let clock_lck = CLOCK.lock().unwrap();
+        clock_lck.start.map_or(0.0, |start| start.elapsed().as_secs_f32())
+    }
+
+    /// Advances master clock by specified time in s. Used in lockstep mode, where clock does not follow wall time
+    pub fn advance(dt: f32)
+    {
+        let mut clock_lck = CLOCK.lock().unwrap();
+        clock_lck.base_sim_time += dt as f64;
+    }
+
+    /// Returns seed of all random generators. Taken from configuration or drawn randomly if seed is not a number
+    pub fn seed() -> u64
+    {
+        let mut seed_lck = SEED.lock().unwrap();
+        *seed_lck.get_or_insert_with(|| {
+            let seed = ServerConfig::get_opt_u64("seed").unwrap_or_else(rand::random);
+            printLog!("Random seed: {}", seed);
+            seed
+        })
+    }
+
+    /// Checks if simulation runs in deterministic lockstep mode
+    pub fn isLockstep() -> bool
+    {
+        LOCKSTEP.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Enables or disables lockstep mode. Must be called before subsystems are started
+    pub fn setLockstep(lockstep: bool)
+    {
+        LOCKSTEP.store(lockstep, atomic::Ordering::Relaxed);
+    }
+
+    /// Blocks subsystem thread until lockstep driver starts specified phase.
+    /// Returns false if running flag was cleared while waiting.
+    pub fn waitTurn(phase: StepPhase, running: &AtomicBool) -> bool
+    {
+        let mut current = PHASE.lock().unwrap();
+        while *current != phase
+        {
+            if !running.load(atomic::Ordering::SeqCst)
+            {
+                return false;
+            }
+            current = PHASE_CHANGED.wait_timeout(current, PHASE_POLL).unwrap().0;
+        }
+        true
+    }
+
+    /// Marks specified phase as finished by subsystem
+    pub fn finishTurn(phase: StepPhase)
+    {
+        let mut current = PHASE.lock().unwrap();
+        if *current == phase
+        {
+            *current = StepPhase::IDLE;
+            PHASE_CHANGED.notify_all();
+        }
+    }
+
+    /// Starts specified phase and blocks until subsystem finishes it or running flag is cleared.
+    /// Step never proceeds without the phase, late subsystem is reported after timeout.
+    pub fn runPhase(phase: StepPhase, running: &AtomicBool)
+    {
+        let start = Instant::now();
+        let mut reported = false;
+        let mut current = PHASE.lock().unwrap();
+        *current = phase;
+        PHASE_CHANGED.notify_all();
+        while *current == phase
+        {
+            if !running.load(atomic::Ordering::SeqCst)
+            {
+                *current = StepPhase::IDLE;
+                break;
+            }
+            if !reported && start.elapsed() > PHASE_TIMEOUT
+            {
+                printError!("Lockstep phase {:?} is not finished after {:?}, still waiting", phase, PHASE_TIMEOUT);
+                reported = true;
+            }
+            current = PHASE_CHANGED.wait_timeout(current, PHASE_POLL).unwrap().0;
+        }
+    }
+
+    /// Serializes clock to notification message: c:SIM_TIME,WALL_TIME,PAUSED,SCALE
+    pub fn clockMsg() -> String
+    {
+        format!("c:{},{},{},{}", Self::now(), Self::wallTime(), if Self::isPaused() { 1 } else { 0 }, Self::getTimeScale())
+    }
+
+    /// Checks if simulation is paused
+    pub fn isPaused() -> bool
+    {
+        PAUSED.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Returns simulation time scale. Value 1.0 means real time
+    pub fn getTimeScale() -> f32
+    {
+        f32::from_bits(TIME_SCALE.load(atomic::Ordering::Relaxed))
+    }
+
+    /// Pauses or resumes simulation
+    pub fn setPaused(paused: bool)
+    {
+        let mut clock_lck = CLOCK.lock().unwrap();
+        clock_lck.commit();
+        PAUSED.store(paused, atomic::Ordering::Relaxed);
+        drop(clock_lck);
+        printLog!("Simulation {}", if paused { "paused" } else { "resumed" });
+    }
+
+    /// Sets simulation time scale. Scale must be positive
+    pub fn setTimeScale(scale: f32)
+    {
+        assert!(scale > 0.0, "Time scale must be positive");
+        let mut clock_lck = CLOCK.lock().unwrap();
+        clock_lck.commit();
+        TIME_SCALE.store(scale.to_bits(), atomic::Ordering::Relaxed);
+        drop(clock_lck);
+        printLog!("Simulation time scale set to {}", scale);
+    }
+
+    /// Serializes simulation state to control message: p:PAUSED,SCALE
+    /// PAUSED -- 1 if simulation is paused, 0 otherwise
+    /// SCALE -- time scale of simulation, 1.0 means real time
+    pub fn stateMsg() -> String
+    {
+        format!("p:{},{}", if Self::isPaused() { 1 } else { 0 }, Self::getTimeScale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_stops_while_paused_and_follows_scale() {
+        Simulation::start();
+        Simulation::setPaused(true);
+        let paused_at = Simulation::now();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(Simulation::now(), paused_at);
+        Simulation::setTimeScale(4.0);
+        Simulation::setPaused(false);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(Simulation::now() - paused_at >= 0.08);
+        Simulation::setTimeScale(1.0);
+        assert!(Simulation::wallTime() >= 0.04);
+    }
+
+    #[test]
+    fn phase_runs_until_subsystem_finishes_turn() {
+        let running = std::sync::Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let worker = std::thread::spawn(move || {
+            assert!(Simulation::waitTurn(StepPhase::CARGO, &r));
+            Simulation::finishTurn(StepPhase::CARGO);
+            assert!(!Simulation::waitTurn(StepPhase::CARGO, &AtomicBool::new(false)));
+        });
+        let start = Instant::now();
+        Simulation::runPhase(StepPhase::CARGO, &AtomicBool::new(true));
+        assert!(start.elapsed() < PHASE_TIMEOUT);
+        assert_eq!(*PHASE.lock().unwrap(), StepPhase::IDLE);
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+use std::f32::consts::PI;
+use nalgebra::{Vector3, DMatrix};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Length of foot in m
+const FOOT: f32 = 0.3048;
+/// Altitude in ft up to which low altitude model is used
+const LOW_ALTITUDE: f32 = 1000.0;
+/// Altitude in ft from which medium/high altitude model is used
+const HIGH_ALTITUDE: f32 = 2000.0;
+/// Minimal altitude in ft of low altitude model
+const MIN_ALTITUDE: f32 = 10.0;
+/// Turbulence scale length in ft at medium/high altitude
+const HIGH_ALTITUDE_SCALE: f32 = 1750.0;
+/// Minimal airspeed in m/s used in filters. Hovering drone is still passed by turbulence
+const MIN_AIRSPEED: f32 = 1.0;
+/// Substream of gust filter. Random walk uses substreams 0-2, one per axis
+const GUST_SUBSTREAM: u64 = 3;
+/// Relative change of normalized step for which discrete filter is reused. Absorbs rounding of simulation time
+const STEP_TOLERANCE: f32 = 1e-3;
+
+/// Rational shaping filter N(Ts)/D(Ts) of turbulence spectrum, T = L/V.
+/// Coefficients are given for increasing powers of Ts, numerator has lower order than denominator
+struct Shaping
+{
+    num: &'static [f32],
+    den: &'static [f32],
+}
+
+/// Dryden longitudinal filter, MIL-F-8785C: sigma_u * sqrt(2L_u/(pi V)) / (1 + T s)
+const DRYDEN_U: Shaping = Shaping { num: &[1.0], den: &[1.0, 1.0] };
+/// Dryden lateral and vertical filter, MIL-F-8785C: sigma * sqrt(L/(pi V)) * (1 + sqrt(3) T s) / (1 + T s)^2
+const DRYDEN_VW: Shaping = Shaping { num: &[1.0, 1.732_050_8], den: &[1.0, 2.0, 1.0] };
+/// von Kármán longitudinal filter, rational approximation of MIL-F-8785C / MIL-HDBK-1797:
+/// sigma_u * sqrt(2L_u/(pi V)) * (1 + 0.25 T s) / (1 + 1.357 T s + 0.1987 (T s)^2)
+const VON_KARMAN_U: Shaping = Shaping { num: &[1.0, 0.25], den: &[1.0, 1.357, 0.1987] };
+/// von Kármán lateral and vertical filter, rational approximation of MIL-F-8785C / MIL-HDBK-1797:
+/// sigma * sqrt(L/(pi V)) * (1 + 2.7478 T s + 0.3398 (T s)^2) / (1 + 2.9958 T s + 1.9754 (T s)^2 + 0.1539 (T s)^3)
+const VON_KARMAN_VW: Shaping = Shaping { num: &[1.0, 2.7478, 0.3398], den: &[1.0, 2.9958, 1.9754, 0.1539] };
+
+/// Turbulence model selected in configuration
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TurbulenceModel
+{
+    /// clamped random walk scaled by wind_turbulence
+    RANDOM,
+    DRYDEN,
+    KARMAN,
+}
+
+impl TurbulenceModel
+{
+    /// Parses model name from configuration: random_walk, dryden or von_karman
+    pub fn parse(name: &str) -> Option<Self>
+    {
+        match name.trim().to_lowercase().as_str() {
+            "random_walk" => Some(TurbulenceModel::RANDOM),
+            "dryden" => Some(TurbulenceModel::DRYDEN),
+            "von_karman" => Some(TurbulenceModel::KARMAN),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of Dryden and von Kármán turbulence
+#[derive(Copy, Clone, Debug)]
+pub struct TurbulenceParams
+{
+    pub model: TurbulenceModel,
+    /// wind speed at 20 ft (6.1 m) in m/s. Sets turbulence intensity at low altitude
+    pub wind_20ft: f32,
+    /// turbulence intensity at medium/high altitude in m/s
+    pub sigma_high: f32,
+    /// characteristic span of aircraft in m. Sets angular gusts
+    pub span: f32,
+}
+
+/// Seeded random walk turbulence stream
+pub struct RandomWalk
+{
+    /// current turbulence vector in m/s
+    value: Vector3<f32>,
+    /// random generator per axis
+    rng: [StdRng; 3],
+}
+
+impl RandomWalk
+{
+    /// Constructor. Streams with different numbers are independent for the same seed
+    pub fn new(seed: u64, stream: u64) -> Self
+    {
+        RandomWalk {
+            value: Vector3::zeros(),
+            rng: std::array::from_fn(|i| StdRng::seed_from_u64(splitmix(splitmix(seed, stream), i as u64)))
+        }
+    }
+
+    /// Returns next turbulance vector
+    pub fn next(&mut self, turbulence_scale: f32) -> Vector3<f32>
+    {
+        if turbulence_scale < f32::EPSILON
+        {
+            return self.value;
+        }
+        for i in 0..self.value.len()
+        {
+            self.value[i] += self.rng[i].gen_range(-turbulence_scale..turbulence_scale);
+            self.value[i] = self.value[i].clamp(-3.0 * turbulence_scale, 3.0 * turbulence_scale);
+        }
+        self.value
+    }
+}
+
+/// Dryden or von Kármán gust generator of single drone. White noise is shaped by transfer functions
+/// of MIL-F-8785C with intensities and scale lengths depending on altitude and airspeed.
+/// Gust components are computed in wind axes (u along horizontal airspeed, w down)
+/// and returned in world NED frame.
+pub struct GustFilter
+{
+    rng: StdRng,
+    /// filters of u, v, w channels
+    linear: [ShapingState; 3],
+    /// state of roll rate filter
+    roll_state: ShapingState,
+    /// roll, pitch and yaw gust rates in wind axes
+    roll: f32,
+    pitch: f32,
+    yaw: f32,
+    /// linear gust of previous step in wind axes
+    last: Vector3<f32>,
+    /// simulation time of previous step
+    last_time: Option<f32>,
+}
+
+impl GustFilter
+{
+    /// Constructor. Streams with different numbers are independent for the same seed
+    pub fn new(seed: u64, stream: u64) -> Self
+    {
+        GustFilter { rng: StdRng::seed_from_u64(splitmix(splitmix(seed, stream), GUST_SUBSTREAM)), linear: Default::default(),
+            roll_state: ShapingState::default(), roll: 0.0, pitch: 0.0, yaw: 0.0, last: Vector3::zeros(), last_time: None }
+    }
+
+    /// Advances filters to specified simulation time. Air velocity is velocity of drone relative to mean wind.
+    /// Returns linear gust in m/s and angular gust in rad/s, both in world NED frame
+    pub fn next(&mut self, params: &TurbulenceParams, time: f32, altitude: f32, air_velocity: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>)
+    {
+        let dt = time - self.last_time.unwrap_or(time);
+        self.last_time = Some(time);
+        let axes = windAxes(air_velocity);
+        if dt <= 0.0
+        {
+            return (axes * self.last, axes * Vector3::new(self.roll, self.pitch, -self.yaw));
+        }
+        let airspeed = air_velocity.norm().max(MIN_AIRSPEED);
+        let (sigma, length) = intensity(altitude, params.wind_20ft, params.sigma_high);
+        let (shaping_u, shaping_vw) = match params.model {
+            TurbulenceModel::KARMAN => (&VON_KARMAN_U, &VON_KARMAN_VW),
+            _ => (&DRYDEN_U, &DRYDEN_VW),
+        };
+        let mut gust = Vector3::zeros();
+        for i in 0..3
+        {
+            let (shaping, scale) = if i == 0 { (shaping_u, 2.0) } else { (shaping_vw, 1.0) };
+            let time_unit = length[i] / airspeed;
+            let gain = sigma[i] * (scale * time_unit / PI).sqrt();
+            let noise = gaussian(&mut self.rng);
+            gust[i] = self.linear[i].next(shaping, time_unit, dt, gain, noise);
+        }
+
+        // Angular gusts, Dryden form of MIL-F-8785C
+        let span = params.span;
+        let gain_p = sigma[2] * (0.8 / airspeed).sqrt() * (PI / (4.0 * span)).powf(1.0 / 6.0) / length[2].cbrt();
+        let noise = gaussian(&mut self.rng);
+        self.roll = self.roll_state.next(&DRYDEN_U, 4.0 * span / PI / airspeed, dt, gain_p, noise);
+        let a_pitch = (-dt * PI * airspeed / (4.0 * span)).exp();
+        self.pitch = a_pitch * self.pitch + (1.0 - a_pitch) * (gust[2] - self.last[2]) / (airspeed * dt);
+        let a_yaw = (-dt * PI * airspeed / (3.0 * span)).exp();
+        self.yaw = a_yaw * self.yaw + (1.0 - a_yaw) * (gust[1] - self.last[1]) / (airspeed * dt);
+        self.last = gust;
+        (axes * gust, axes * Vector3::new(self.roll, self.pitch, -self.yaw))
+    }
+}
+
+/// State of shaping filter with its discrete form for last normalized step
+#[derive(Default)]
+struct ShapingState
+{
+    state: [f32; 3],
+    /// step normalized by time unit and discrete system for it. Filter keeps the same shaping for whole life
+    discrete: Option<(f32, DMatrix<f32>)>,
+}
+
+impl ShapingState
+{
+    /// Advances shaping filter driven by white noise sample held for dt. Filter is realized in controllable
+    /// canonical form in time normalized by time_unit and discretized exactly with zero-order hold.
+    /// Noise is scaled so that output spectrum is gain^2 |N/D|^2 of the specification. Returns filter output
+    fn next(&mut self, shaping: &Shaping, time_unit: f32, dt: f32, gain: f32, noise: f32) -> f32
+    {
+        let order = shaping.den.len() - 1;
+        let leading = shaping.den[order];
+        let step = dt / time_unit;
+        if self.discrete.as_ref().is_none_or(|(cached, _)| (cached - step).abs() > STEP_TOLERANCE * step)
+        {
+            let mut system = DMatrix::<f32>::zeros(order + 1, order + 1);
+            for i in 0..order - 1
+            {
+                system[(i, i + 1)] = 1.0;
+            }
+            for i in 0..order
+            {
+                system[(order - 1, i)] = -shaping.den[i] / leading;
+            }
+            system[(order - 1, order)] = 1.0;
+            self.discrete = Some((step, (system * step).exp()));
+        }
+        let discrete = &self.discrete.as_ref().unwrap().1;
+        // White noise of unit spectral density in rad/s sampled with period dt
+        let input = noise * (PI / dt).sqrt();
+        let previous = self.state;
+        let mut output = 0.0;
+        for i in 0..order
+        {
+            self.state[i] = (0..order).map(|j| discrete[(i, j)] * previous[j]).sum::<f32>() + discrete[(i, order)] * input;
+            output += shaping.num.get(i).map_or(0.0, |b| b / leading) * self.state[i];
+        }
+        if !output.is_finite()
+        {
+            self.state = [0.0; 3];
+            return 0.0;
+        }
+        gain * output
+    }
+}
+
+/// Returns turbulence intensities in m/s and scale lengths in m of u, v, w components for altitude in m
+fn intensity(altitude: f32, wind_20ft: f32, sigma_high: f32) -> (Vector3<f32>, Vector3<f32>)
+{
+    let low = |h: f32| {
+        let factor = 0.177 + 0.000823 * h;
+        let sigma_w = 0.1 * wind_20ft;
+        let length_uv = h / factor.powf(1.2);
+        (Vector3::new(sigma_w / factor.powf(0.4), sigma_w / factor.powf(0.4), sigma_w),
+            Vector3::new(length_uv, length_uv, h) * FOOT)
+    };
+    let high = (Vector3::repeat(sigma_high), Vector3::repeat(HIGH_ALTITUDE_SCALE * FOOT));
+    let h = (altitude / FOOT).max(MIN_ALTITUDE);
+    if h <= LOW_ALTITUDE
+    {
+        return low(h);
+    }
+    if h >= HIGH_ALTITUDE
+    {
+        return high;
+    }
+    let t = (h - LOW_ALTITUDE) / (HIGH_ALTITUDE - LOW_ALTITUDE);
+    let (sigma_low, length_low) = low(LOW_ALTITUDE);
+    (sigma_low.lerp(&high.0, t), length_low.lerp(&high.1, t))
+}
+
+/// Returns rotation from wind axes to world NED frame. Columns: u along horizontal airspeed, v to the right, w down
+fn windAxes(air_velocity: &Vector3<f32>) -> nalgebra::Matrix3<f32>
+{
+    let horizontal = Vector3::new(air_velocity.x, air_velocity.y, 0.0);
+    let u = if horizontal.norm() > f32::EPSILON { horizontal.normalize() } else { Vector3::x() };
+    let w = Vector3::z();
+    nalgebra::Matrix3::from_columns(&[u, w.cross(&u), w])
+}
+
+/// Derives seed of independent stream from seed and stream number with SplitMix64 mixing.
+/// Nearby seeds and stream numbers give unrelated results, so streams do not overlap
+fn splitmix(seed: u64, stream: u64) -> u64
+{
+    let mut z = seed.wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Returns sample of standard normal distribution (Box-Muller)
+fn gaussian(rng: &mut StdRng) -> f32
+{
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_walk_is_reproducible_for_seed() {
+        let mut first = RandomWalk::new(42, 1);
+        let mut second = RandomWalk::new(42, 1);
+        let mut other = RandomWalk::new(42, 2);
+        // Nearby seed with lower stream number gives different generators
+        let mut shifted = RandomWalk::new(45, 0);
+        for _ in 0..10
+        {
+            let value = first.next(2.0);
+            assert_eq!(value, second.next(2.0));
+            assert_ne!(value, other.next(2.0));
+            assert_ne!(value, shifted.next(2.0));
+            assert!(value.amax() <= 6.0);
+        }
+    }
+
+    #[test]
+    fn gust_intensity_follows_model() {
+        for model in [TurbulenceModel::DRYDEN, TurbulenceModel::KARMAN]
+        {
+            let params = TurbulenceParams { model, wind_20ft: 10.0, sigma_high: 1.5, span: 1.0 };
+            let mut filter = GustFilter::new(7, 1);
+            let air_velocity = Vector3::new(0.0, 10.0, 0.0);
+            let mut sum_sq = Vector3::<f32>::zeros();
+            let steps = 20000;
+            for k in 0..=steps
+            {
+                let (gust, angular) = filter.next(&params, k as f32 * 0.05, 50.0, &air_velocity);
+                sum_sq += gust.component_mul(&gust);
+                assert!(angular.iter().all(|v| v.is_finite()));
+            }
+            // At 50 m vertical intensity is 0.1 * W20, horizontal ones are larger. Flight along y
+            let (sigma, _) = intensity(50.0, 10.0, 1.5);
+            let rms = (sum_sq / steps as f32).map(|v| v.sqrt());
+            assert!((rms.z - sigma.z).abs() < 0.25 * sigma.z, "{:?} {:?}", model, rms);
+            assert!((rms.y - sigma.x).abs() < 0.35 * sigma.x, "{:?} {:?}", model, rms);
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use std::{process::{Command, Child, Stdio}, thread::{self, JoinHandle}, sync::{Mutex, Arc}, io::{BufRead, BufReader}};
 use nalgebra::{Vector3,Vector6, SVector, Vector4, geometry::Rotation3};
-use crate::{objects::{Objects, ObjectInfo}, logger, atmosphere::AtmosphereInfo};
+use crate::{objects::{Objects, ObjectInfo, ObjectKind}, logger, atmosphere::AtmosphereInfo, recorder::Recorder, simulation::Simulation};
 use crate::config::DroneConfig;
 use crate::config::ServerConfig;
-use crate::printLog;
+use crate::{printLog, printError};
 
 
 /// State of single drone. Contains parsed information from physic simualtion
@@ -19,12 +19,54 @@ pub struct DroneState
     acc: Vector6<f32>,
     /// rotor angular velocities in rad/s
     om: Vec<f32>,
+    /// master simulation clock time when state was received in s
+    sim_time: f32,
+    /// wall time since server start when state was received in s
+    wall_time: f32,
+    /// difference between master clock and UAV time measured on first update
+    clock_offset: Option<f32>,
 }
 
 impl DroneState {
     /// Constructor
     pub fn new() -> Self {
-        DroneState {time: 0.0, pos: SVector::repeat(0.0f32), vel: Vector6::repeat(0.0f32), acc: Vector6::repeat(0.0f32), om: Vec::new()}
+        DroneState {time: 0.0, pos: SVector::repeat(0.0f32), vel: Vector6::repeat(0.0f32), acc: Vector6::repeat(0.0f32), om: Vec::new(),
+            sim_time: 0.0, wall_time: 0.0, clock_offset: None}
+    }
+
+    /// Get master simulation clock time when state was received in s
+    pub fn getSimTime(&self) -> f32
+    {
+        self.sim_time
+    }
+
+    /// Get wall time since server start when state was received in s
+    pub fn getWallTime(&self) -> f32
+    {
+        self.wall_time
+    }
+
+    /// Get how much UAV time lags behind master clock in s. Offset between clocks is measured on first update
+    pub fn getClockLag(&self, master_time: f32) -> f32
+    {
+        self.clock_offset.map_or(0.0, |offset| master_time - offset - self.time)
+    }
+
+    /// Get time of simulation in s
+    pub fn getTime(&self) -> f32
+    {
+        self.time
+    }
+
+    /// Get full state as flat vector: position, orientation, velocities, accelerations and rotor angular velocities
+    pub fn getTelemetry(&self) -> Vec<f32>
+    {
+        let mut telemetry = Vec::with_capacity(19 + self.om.len());
+        telemetry.extend(self.pos.iter());
+        telemetry.extend(self.vel.iter());
+        telemetry.extend(self.acc.iter());
+        telemetry.extend(self.om.iter());
+        telemetry
     }
 
     /// Get UAV position in meters
@@ -75,7 +117,7 @@ impl DroneState {
     }
 }
 
-/// Serializes drone state to string
+/// Serializes drone state to string: time,x,y,z,q0,qx,qy,qz,vx,vy,vz,wx,wy,wz,om...,sim_time,wall_time
 impl ToString for DroneState {
     fn to_string(&self) -> String {
         let mut result = String::with_capacity(200);
@@ -96,11 +138,84 @@ impl ToString for DroneState {
                 result.push(','); 
             }
         }
-        result.pop();
+        result.push_str(&self.sim_time.to_string());
+        result.push(',');
+        result.push_str(&self.wall_time.to_string());
         result
     }
 }
 
+/// Handle to control channel of UAV process. Commands can be sent through it without locking list of UAVs
+#[derive(Clone)]
+pub struct UavControl
+{
+    pub id: usize,
+    /// control socket and flag if UAV process still accepts commands
+    socket: Arc<Mutex<(zmq::Socket, bool)>>,
+}
+
+impl UavControl
+{
+    /// Send control message to control process. Message is dropped if UAV was removed
+    fn send(&self, msg_str: &str) -> String
+    {
+        let socket_lck = self.socket.lock().unwrap();
+        let (socket, alive) = &*socket_lck;
+        if !alive
+        {
+            return String::new();
+        }
+        socket.send(&msg_str, 0).unwrap();
+        let mut msg = zmq::Message::new();
+        if socket.recv(&mut msg, 0).is_ok()
+        {
+            let rep = msg.as_str().unwrap();
+            //printLog!("{}", msg_str);
+            assert!(rep.contains("ok"));
+            rep.to_string()
+        }
+        else {
+            printError!("Error while sending: {}", msg_str);
+            String::new()
+        }      
+    }
+
+    /// Stops sending messages. Waits for message in progress
+    fn close(&self)
+    {
+        self.socket.lock().unwrap().1 = false;
+    }
+
+    /// Send atmosphere information to UAV:
+    /// a:WIND_X,WIND_Y,WIND_Z,TEMPERATURE,PRESSURE,DENSITY,GUST_P,GUST_Q,GUST_R,SPEED_OF_SOUND,VISCOSITY
+    pub fn sendAtmosphereInfo(&self, info: &AtmosphereInfo)
+    {
+        let mut command = String::with_capacity(60);
+        command.push_str("a:");
+        command.push_str(&info.wind[0].to_string());
+        command.push(',');
+        command.push_str(&info.wind[1].to_string());
+        command.push(',');
+        command.push_str(&info.wind[2].to_string());
+        command.push(',');
+        command.push_str(&info.air_temperature.to_string());
+        command.push(',');
+        command.push_str(&info.air_pressure.to_string());
+        command.push(',');
+        command.push_str(&info.air_density.to_string());
+        for rate in info.wind_angular.iter()
+        {
+            command.push(',');
+            command.push_str(&rate.to_string());
+        }
+        command.push(',');
+        command.push_str(&info.speed_of_sound.to_string());
+        command.push(',');
+        command.push_str(&info.dynamic_viscosity.to_string());
+        self.send(&command);
+    }
+}
+
 /// Representation of single UAV
 pub struct UAV
 {
@@ -113,7 +228,7 @@ pub struct UAV
     simulationListener: Option<(JoinHandle<()>,JoinHandle<()>)>,
     controllerListener: Option<(JoinHandle<()>,JoinHandle<()>)>,
     steer_socket: zmq::Socket,
-    control_socket: zmq::Socket,
+    control: UavControl,
     state_listener: Option<JoinHandle<()>>
 }
 
@@ -123,20 +238,30 @@ impl UAV
     pub fn new(_ctx: &mut zmq::Context,id : usize , name: &str, config_path: &str, state: Arc<Mutex<DroneState>>, objects: Arc<Mutex<Objects>>) -> Self {
         let config = DroneConfig::parse(&config_path).expect("Config file error");
 
-        let simulation = Command::new("../UAV_physics_engine/build/uav")
-            .arg("-c").arg(&config_path)
+        let mut simulation = Command::new("../UAV_physics_engine/build/uav");
+        simulation.arg("-c").arg(&config_path)
             .arg("-n").arg(name)
             .arg("--dt").arg(ServerConfig::get_usize("uav_physic_step_time").to_string())
-            .arg("--ode").arg(ServerConfig::get_str("uav_physic_ode_solver"))
+            .arg("--ode").arg(ServerConfig::get_str("uav_physic_ode_solver"));
+        if Simulation::isLockstep()
+        {
+            simulation.arg("--lockstep");
+        }
+        let simulation = simulation
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
             .expect("failed to execute simulation process");
 
-        let controller = Command::new("../UAV_controller/build/controller")
-            .arg("-c").arg(&config_path)
+        let mut controller = Command::new("../UAV_controller/build/controller");
+        controller.arg("-c").arg(&config_path)
             .arg("-n").arg(name)
-            .arg("--dt").arg(ServerConfig::get_usize("uav_control_step_time").to_string())
+            .arg("--dt").arg(ServerConfig::get_usize("uav_control_step_time").to_string());
+        if Simulation::isLockstep()
+        {
+            controller.arg("--lockstep");
+        }
+        let controller = controller
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -171,7 +296,7 @@ impl UAV
                 {
                     if let Ok(content) = line
                     {
-                        logger::Logger::print(&drone_name2, &source_name2, logger::COLOR_RED, &content);
+                        logger::Logger::printLevel(logger::LogLevel::WARN, &drone_name2, &source_name2, logger::COLOR_RED, &content);
                     }
                 }
             }))
@@ -202,15 +327,15 @@ impl UAV
             steer_socket:  _ctx.socket(zmq::REQ)
                                 .expect("creating socket error"),
 
-            control_socket:  _ctx.socket(zmq::REQ)
-                                .expect("creating socket error"),
+            control: UavControl { id, socket: Arc::new(Mutex::new((_ctx.socket(zmq::REQ)
+                                .expect("creating socket error"), true))) },
 
             state_listener: Option::None
         };
 
         uav.steer_socket.connect(&format!("ipc:///tmp/{}/steer",uav.name.to_owned()))
                         .expect("steer connect error");
-        uav.control_socket.connect(&format!("ipc:///tmp/{}/control",uav.name.to_owned()))
+        uav.control.socket.lock().unwrap().0.connect(&format!("ipc:///tmp/{}/control",uav.name.to_owned()))
                         .expect("control connect error");
 
         UAV::startListeners(_ctx, &mut uav, state);
@@ -267,6 +392,8 @@ impl UAV
         let vel_socket = buildSocket("vn");
         let acc_socket = buildSocket("ab");
         let om_socket = buildSocket("om");
+        let id = uav.id;
+        let name = uav.name.clone();
 
         uav.state_listener = Option::Some(thread::spawn(move || {
             let mut msg = zmq::Message::new();
@@ -320,6 +447,12 @@ impl UAV
                 if let Some(t_val) = t
                 {
                     state.time = t_val;
+                    state.sim_time = Simulation::now();
+                    state.wall_time = Simulation::wallTime();
+                    if state.clock_offset.is_none()
+                    {
+                        state.clock_offset = Some(state.sim_time - t_val);
+                    }
                 }
                 if let Some(pos_val) = pos
                 {
@@ -337,6 +470,10 @@ impl UAV
                 {
                     state.om = om_val;
                 }
+                if t.is_some()
+                {
+                    Recorder::recordDrone(&name, id, state.time, state.sim_time, state.wall_time, &state.getTelemetry());
+                }
                 drop(state);
                 //thread::sleep(time::Duration::from_millis(10));
             }
@@ -349,41 +486,31 @@ impl UAV
         self.steer_socket.send(&msg, 0).unwrap();
     }
 
-    /// Send control message to control process
-    fn _sendControlMsg(&self, msg_str: &str) -> String
+    /// Sends steering message to control process and waits for its reply
+    fn _requestSteeringMsg(&self, msg_str: &str) -> String
     {
-        self.control_socket.send(&msg_str, 0).unwrap();
+        self._sendSteeringMsg(msg_str);
         let mut msg = zmq::Message::new();
-        if self.control_socket.recv(&mut msg, 0).is_ok()
+        if self.steer_socket.recv(&mut msg, 0).is_ok()
         {
-            let rep = msg.as_str().unwrap();
-            //printLog!("{}", msg_str);
-            assert!(rep.contains("ok"));
-            rep.to_string()
+            msg.as_str().unwrap_or_default().to_string()
         }
         else {
-            printLog!("Error while sending: {}", msg_str);
+            printError!("Error while sending: {}", msg_str);
             String::new()
-        }      
+        }
     }
 
-    /// Send atmosphere information to UAV
-    pub fn sendAtmosphereInfo(&self, info: &AtmosphereInfo)
+    /// Send control message to control process
+    fn _sendControlMsg(&self, msg_str: &str) -> String
     {
-        let mut command = String::with_capacity(30);
-        command.push_str("a:");
-        command.push_str(&info.wind[0].to_string());
-        command.push(',');
-        command.push_str(&info.wind[1].to_string());
-        command.push(',');
-        command.push_str(&info.wind[2].to_string());
-        command.push(',');
-        command.push_str(&info.air_temperature.to_string());
-        command.push(',');
-        command.push_str(&info.air_pressure.to_string());
-        command.push(',');
-        command.push_str(&info.air_density.to_string());
-        self._sendControlMsg(&command);
+        self.control.send(msg_str)
+    }
+
+    /// Returns handle to control channel of UAV
+    pub fn control(&self) -> UavControl
+    {
+        self.control.clone()
     }
 
     /// Send outer force value to UAV
@@ -447,7 +574,11 @@ impl UAV
         let objects = self.objects_arc.lock().unwrap();
         let info = ObjectInfo{
             model_name: cargo_param.model.clone(),
-            collision_radius: cargo_param.radius
+            collision_radius: cargo_param.radius,
+            owner: self.id,
+            kind: ObjectKind::CARGO,
+            mass: cargo_param.mass,
+            marker: false,
         };
         let id = objects.addObj(cargo_param.mass, cargo_param.CS, pos, vel, info);
         drop(objects);
@@ -494,7 +625,11 @@ impl UAV
         drop(state);
         let info = ObjectInfo{
             model_name: ammo_param.model.clone(),
-            collision_radius: ammo_param.radius
+            collision_radius: ammo_param.radius,
+            owner: self.id,
+            kind: ObjectKind::AMMO,
+            mass: ammo_param.mass,
+            marker: ammo_param.marker,
         };
         let objects = self.objects_arc.lock().unwrap();
         let id = objects.addObj(ammo_param.mass, ammo_param.CS, pos, vel,info);
@@ -529,6 +664,20 @@ impl UAV
         self._sendControlMsg(&command);
     }
 
+    /// Sends simulation pause and time scale to UAV processes
+    pub fn sendSimulationState(&self, msg: &str)
+    {
+        self._sendControlMsg(msg);
+    }
+
+    /// Sends command to advance UAV simulation and controller by specified step in ms. Used in lockstep mode
+    pub fn sendStep(&self, step_ms: usize)
+    {
+        let command = format!("n:{}", step_ms);
+        self._sendControlMsg(&command);
+        self._requestSteeringMsg(&command);
+    }
+
     /// Sends command to start jet engine
     pub fn sendStartJet(&self, index: usize)
     {
@@ -545,6 +694,7 @@ impl UAV
 impl Drop for UAV {
     fn drop(&mut self) {
         printLog!("Dropping drone: {}", self.name);
+        self.control.close();
         self._sendSteeringMsg("c:exit");
         let sim = self.simulationListener.take().unwrap();
         sim.0.join().expect("sim cout wait");
@@ -0,0 +1,216 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use nalgebra::Vector3;
+use serde_yaml::Value;
+use crate::airflow::AirSource;
+
+/// Weather in single moment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeatherState
+{
+    /// base wind vector in m/s
+    pub wind: Vector3<f32>,
+    /// air temperature at sea level in K
+    pub temperature: f32,
+    /// air pressure at sea level in Pa
+    pub pressure: f32,
+}
+
+/// Weather keyframe. Weather between keyframes is interpolated linearly
+struct Keyframe
+{
+    /// simulation time in s
+    time: f32,
+    state: WeatherState,
+    message: String,
+}
+
+/// Gust event added to base wind. Gust rises and falls with 1-cos shape
+struct GustEvent
+{
+    /// simulation time of gust start in s
+    time: f32,
+    /// gust duration in s
+    duration: f32,
+    /// peak gust vector in m/s
+    wind: Vector3<f32>,
+    message: String,
+}
+
+/// Scripted weather scenario: timeline of keyframes and gust events.
+/// Scenario is YAML file:
+/// keyframes: list of {time, wind: [x, y, z], temperature, pressure, message}
+/// gusts: list of {time, duration, wind: [x, y, z], message}
+/// sources: list of local air flow sources (thermals, downdrafts, rotor wash), see AirSource::parse
+/// Keyframe fields except time are optional, missing values are taken from previous keyframe.
+/// Messages are optional and announced when keyframe, gust or source starts.
+pub struct Weather
+{
+    keyframes: Vec<Keyframe>,
+    gusts: Vec<GustEvent>,
+    sources: Vec<AirSource>,
+}
+
+impl Weather
+{
+    /// Constant weather without scenario
+    pub fn constant(state: WeatherState) -> Self
+    {
+        Weather { keyframes: vec![Keyframe { time: 0.0, state, message: String::new() }], gusts: Vec::new(), sources: Vec::new() }
+    }
+
+    /// Loads scenario from file. Initial state is used before the first keyframe and for its missing values
+    pub fn load(path: &str, initial: WeatherState) -> Result<Self, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let scenario: Value = serde_yaml::from_reader(file).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&scenario, initial).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Parses scenario from YAML value
+    fn parse(scenario: &Value, initial: WeatherState) -> Result<Self, String>
+    {
+        let mut weather = Self::constant(initial);
+        let mut state = initial;
+        for (i, keyframe) in list(&scenario["keyframes"]).iter().enumerate()
+        {
+            let time = number(&keyframe["time"]).ok_or(format!("Keyframe {}: missing time", i))?;
+            if let Some(wind) = vector(&keyframe["wind"])
+            {
+                state.wind = wind;
+            }
+            state.temperature = number(&keyframe["temperature"]).unwrap_or(state.temperature);
+            state.pressure = number(&keyframe["pressure"]).unwrap_or(state.pressure);
+            let message = keyframe["message"].as_str().unwrap_or("").to_string();
+            if time <= 0.0
+            {
+                weather.keyframes.clear();
+            }
+            weather.keyframes.push(Keyframe { time: time.max(0.0), state, message });
+        }
+        for (i, gust) in list(&scenario["gusts"]).iter().enumerate()
+        {
+            let time = number(&gust["time"]).ok_or(format!("Gust {}: missing time", i))?;
+            let duration = number(&gust["duration"]).filter(|d| *d > 0.0).ok_or(format!("Gust {}: missing or invalid duration", i))?;
+            let wind = vector(&gust["wind"]).ok_or(format!("Gust {}: missing wind", i))?;
+            let message = gust["message"].as_str().unwrap_or("").to_string();
+            weather.gusts.push(GustEvent { time, duration, wind, message });
+        }
+        for source in list(&scenario["sources"]).iter()
+        {
+            weather.sources.push(AirSource::parse(source)?);
+        }
+        if weather.keyframes.windows(2).any(|pair| pair[1].time <= pair[0].time)
+        {
+            return Err("Keyframes must be sorted by time".to_string());
+        }
+        Ok(weather)
+    }
+
+    /// Returns weather in specified simulation time
+    pub fn at(&self, time: f32) -> WeatherState
+    {
+        let next = self.keyframes.iter().position(|keyframe| keyframe.time > time);
+        let mut state = match next {
+            Some(0) => self.keyframes[0].state,
+            Some(i) => {
+                let (from, to) = (&self.keyframes[i - 1], &self.keyframes[i]);
+                let t = (time - from.time) / (to.time - from.time);
+                WeatherState {
+                    wind: from.state.wind.lerp(&to.state.wind, t),
+                    temperature: from.state.temperature + (to.state.temperature - from.state.temperature) * t,
+                    pressure: from.state.pressure + (to.state.pressure - from.state.pressure) * t,
+                }
+            }
+            None => self.keyframes.last().unwrap().state,
+        };
+        for gust in self.gusts.iter().filter(|gust| time >= gust.time && time <= gust.time + gust.duration)
+        {
+            state.wind += gust.wind * 0.5 * (1.0 - (2.0 * PI * (time - gust.time) / gust.duration).cos());
+        }
+        state
+    }
+
+    /// Returns wind induced by local air flow sources in specified position and time
+    pub fn localWind(&self, pos: &Vector3<f32>, time: f32) -> Vector3<f32>
+    {
+        self.sources.iter().map(|source| source.wind(pos, time)).sum()
+    }
+
+    /// Returns messages of keyframes, gusts and sources that start in time range (from, to]
+    pub fn events(&self, from: f32, to: f32) -> Vec<&str>
+    {
+        self.keyframes.iter().map(|keyframe| (keyframe.time, keyframe.message.as_str()))
+            .chain(self.gusts.iter().map(|gust| (gust.time, gust.message.as_str())))
+            .chain(self.sources.iter().map(|source| (source.start, source.message.as_str())))
+            .filter(|(time, message)| *time > from && *time <= to && !message.is_empty())
+            .map(|(_, message)| message)
+            .collect()
+    }
+
+    /// Checks if any keyframe, gust or source starts or ends in time range (from, to]
+    pub fn changed(&self, from: f32, to: f32) -> bool
+    {
+        self.keyframes.iter().map(|keyframe| keyframe.time)
+            .chain(self.gusts.iter().flat_map(|gust| [gust.time, gust.time + gust.duration]))
+            .chain(self.sources.iter().flat_map(|source| [source.start, source.start + source.lifetime]))
+            .any(|time| time > from && time <= to)
+    }
+}
+
+/// Serializes weather state to notification message: w:WIND_X,WIND_Y,WIND_Z,TEMPERATURE,PRESSURE
+pub fn weatherMsg(state: &WeatherState) -> String
+{
+    format!("w:{},{},{},{},{}", state.wind.x, state.wind.y, state.wind.z, state.temperature, state.pressure)
+}
+
+/// Returns YAML sequence or empty list
+fn list(value: &Value) -> Vec<Value>
+{
+    value.as_sequence().cloned().unwrap_or_default()
+}
+
+/// Returns YAML number as f32
+fn number(value: &Value) -> Option<f32>
+{
+    value.as_f64().map(|v| v as f32)
+}
+
+/// Returns YAML sequence of three numbers as vector
+fn vector(value: &Value) -> Option<Vector3<f32>>
+{
+    let values = value.as_sequence()?.iter().map(number).collect::<Option<Vec<f32>>>()?;
+    (values.len() == 3).then(|| Vector3::new(values[0], values[1], values[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weather_is_interpolated_between_keyframes() {
+        let scenario: Value = serde_yaml::from_str("
+keyframes:
+  - time: 10
+    wind: [4, 0, 0]
+    message: Front
+  - time: 20
+    temperature: 278.15
+gusts:
+  - time: 30
+    duration: 2
+    wind: [0, 6, 0]
+    message: Gust
+").unwrap();
+        let initial = WeatherState { wind: Vector3::zeros(), temperature: 288.15, pressure: 101300.0 };
+        let weather = Weather::parse(&scenario, initial).unwrap();
+        assert_eq!(weather.at(0.0), initial);
+        assert_eq!(weather.at(5.0).wind, Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(weather.at(15.0).temperature, 283.15);
+        assert_eq!(weather.at(31.0).wind, Vector3::new(4.0, 6.0, 0.0));
+        assert_eq!(weather.at(40.0).wind, Vector3::new(4.0, 0.0, 0.0));
+        assert_eq!(weather.events(9.0, 31.0), vec!["Front", "Gust"]);
+        assert!(weather.changed(19.0, 20.0));
+        assert!(!weather.changed(21.0, 29.0));
+    }
+}
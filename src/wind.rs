@@ -0,0 +1,122 @@
+use std::fs::read_to_string;
+use nalgebra::Vector3;
+
+/// Regular 3D grid of wind vectors loaded from map folder. Wind between nodes is interpolated trilinearly.
+/// File is CSV. Empty lines and lines starting with # are skipped. First line describes grid:
+/// grid,ORIGIN_X,ORIGIN_Y,ORIGIN_Z,STEP_X,STEP_Y,STEP_Z,NX,NY,NZ
+/// Every next line sets wind in one node: I,J,K,WIND_X,WIND_Y,WIND_Z
+/// Node (I,J,K) is at ORIGIN + (I*STEP_X, J*STEP_Y, K*STEP_Z) in NED world frame. All nodes must be set.
+pub struct WindGrid
+{
+    /// position of node (0,0,0) in m
+    origin: Vector3<f32>,
+    /// distance between nodes in m
+    step: Vector3<f32>,
+    /// number of nodes along axes
+    size: [usize; 3],
+    /// wind vectors in m/s, x index changes the fastest
+    data: Vec<Vector3<f32>>,
+}
+
+impl WindGrid
+{
+    /// Loads wind grid from file
+    pub fn load(path: &str) -> Result<Self, String>
+    {
+        let content = read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Parses wind grid from file content
+    pub fn parse(content: &str) -> Result<Self, String>
+    {
+        let mut lines = content.lines().enumerate()
+            .map(|(no, line)| (no + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let (_, header) = lines.next().ok_or("Missing grid header")?;
+        let header: Vec<&str> = header.split(',').map(|v| v.trim()).collect();
+        if header.len() != 10 || header[0] != "grid"
+        {
+            return Err("Invalid grid header".to_string());
+        }
+        let numbers = header[1..7].iter().map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>().map_err(|e| format!("Invalid grid header: {}", e))?;
+        let size = header[7..10].iter().map(|v| v.parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>().map_err(|e| format!("Invalid grid header: {}", e))?;
+        let step = Vector3::new(numbers[3], numbers[4], numbers[5]);
+        if size.iter().any(|n| *n < 2) || step.iter().any(|s| *s <= 0.0)
+        {
+            return Err("Grid needs at least 2 nodes and positive step along every axis".to_string());
+        }
+        let size = [size[0], size[1], size[2]];
+        let mut data = vec![Vector3::repeat(f32::NAN); size[0] * size[1] * size[2]];
+        for (no, line) in lines
+        {
+            let values: Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+            if values.len() != 6
+            {
+                return Err(format!("Line {}: expected 6 values", no));
+            }
+            let index = values[..3].iter().map(|v| v.parse::<usize>())
+                .collect::<Result<Vec<usize>, _>>().map_err(|e| format!("Line {}: {}", no, e))?;
+            let wind = values[3..].iter().map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>().map_err(|e| format!("Line {}: {}", no, e))?;
+            if index.iter().zip(size.iter()).any(|(i, n)| i >= n)
+            {
+                return Err(format!("Line {}: node out of grid", no));
+            }
+            data[index[0] + size[0] * (index[1] + size[1] * index[2])] = Vector3::new(wind[0], wind[1], wind[2]);
+        }
+        if data.iter().any(|wind| wind.x.is_nan())
+        {
+            return Err("Not all grid nodes are set".to_string());
+        }
+        Ok(WindGrid { origin: Vector3::new(numbers[0], numbers[1], numbers[2]), step, size, data })
+    }
+
+    /// Returns wind interpolated in specified position or None if position is outside grid
+    pub fn sample(&self, pos: &Vector3<f32>) -> Option<Vector3<f32>>
+    {
+        let mut index = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+        for axis in 0..3
+        {
+            let cell = (pos[axis] - self.origin[axis]) / self.step[axis];
+            let last = (self.size[axis] - 1) as f32;
+            if !(0.0..=last).contains(&cell)
+            {
+                return None;
+            }
+            let i = (cell.floor() as usize).min(self.size[axis] - 2);
+            index[axis] = i;
+            frac[axis] = cell - i as f32;
+        }
+        let mut wind = Vector3::zeros();
+        for corner in 0..8
+        {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let weight = (0..3).map(|axis| if offset[axis] == 1 { frac[axis] } else { 1.0 - frac[axis] }).product::<f32>();
+            let (x, y, z) = (index[0] + offset[0], index[1] + offset[1], index[2] + offset[2]);
+            wind += self.data[x + self.size[0] * (y + self.size[1] * z)] * weight;
+        }
+        Some(wind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_is_interpolated_trilinearly() {
+        let mut content = "# test grid\ngrid,0,0,-10,10,10,10,2,2,2\n".to_string();
+        for k in 0..2 { for j in 0..2 { for i in 0..2 {
+            content.push_str(&format!("{},{},{},{},{},0\n", i, j, k, i * 4, k * 2));
+        }}}
+        let grid = WindGrid::parse(&content).unwrap();
+        assert_eq!(grid.sample(&Vector3::new(5.0, 5.0, -5.0)), Some(Vector3::new(2.0, 1.0, 0.0)));
+        assert_eq!(grid.sample(&Vector3::new(10.0, 0.0, 0.0)), Some(Vector3::new(4.0, 2.0, 0.0)));
+        assert_eq!(grid.sample(&Vector3::new(10.1, 0.0, 0.0)), None);
+        assert!(WindGrid::parse("grid,0,0,0,1,1,1,2,2,2\n0,0,0,1,1,1\n").is_err());
+    }
+}
@@ -2,7 +2,6 @@ use core::time;
 use std::panic;
 use std::process::{Command, Child};
 use std::sync::Mutex;
-use libc;
 use regex::Regex;
 use zmq::Socket;
 use serde_json::Value;
@@ -36,25 +35,19 @@ fn teardown() {
 
     let mut thread = process.take().unwrap();
 
-    if let Ok(status) = thread.try_wait()
+    if let Ok(Some(code)) = thread.try_wait()
     {
-        if let Some(code) = status
-        {
-            eprintln!("Main process ended before excepted, and exit with status {}", code);
-        } 
+        eprintln!("Main process ended before excepted, and exit with status {}", code);
     }
 
     let child_pid = thread.id() as libc::pid_t;
     unsafe { libc::kill(child_pid, libc::SIGINT) };
 
     for _ in 0..5 {
-        if let Ok(status) = thread.try_wait()
+        if let Ok(Some(code)) = thread.try_wait()
         {
-            if let Some(code) = status
-            {
-                println!("Main process exitted correctly with status code {}", code);
-                return;
-            } 
+            println!("Main process exitted correctly with status code {}", code);
+            return;
         }
         std::thread::sleep(time::Duration::from_secs(2));
     }
@@ -133,4 +126,25 @@ fn server_reply_on_config_send_correctly() {
     });
     teardown();
     assert!(result.is_ok());
-}
\ No newline at end of file
+}
+
+#[test]
+fn server_reply_on_snapshot_request_correctly() {
+    setup();
+    let result = panic::catch_unwind(|| {
+
+        let request_socket = create_req_sock();
+        request_socket.send("n", 0).expect("Can not send command");
+
+        let responce = request_socket.recv_string(0).expect("Can not recv message").expect("Excepted string");
+        println!("Response: {}", responce);
+        let parsed_json: Value = serde_json::from_str(&responce).expect("Can not parse json");
+        let messages = parsed_json.as_array().expect("Response should be array");
+        for msg in messages
+        {
+            assert!(msg.as_str().expect("Message should be string").contains(':'));
+        }
+    });
+    teardown();
+    assert!(result.is_ok());
+}